@@ -0,0 +1,335 @@
+use crate::formatter::statement_summary;
+use crate::parser::ASTNode;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Finds reads of variables that are never assigned in any scope that could
+/// be visible at that point, without running the program. Every name bound
+/// anywhere in a block counts as visible throughout it, so a read that comes
+/// before its assignment is not reported. Names created at runtime by
+/// `eval` or by an import without `as` cannot be seen and may be reported.
+/// Unreachable code, as found by `unreachable_code`, is reported as well.
+pub fn check_program(nodes: &[ASTNode]) -> Vec<String> {
+    let mut globals: HashSet<Rc<str>> = ["PI".into(), "E".into()].into_iter().collect();
+    collect_globals(nodes, false, &mut globals);
+    let mut checker = Checker {
+        scopes: vec![globals],
+        function: None,
+        problems: Vec::new(),
+    };
+    checker.check_block(nodes, false);
+    let mut problems = checker.problems;
+    problems.extend(unreachable_code(nodes));
+    problems
+}
+
+/// Finds statements that can never run because they follow a `return`,
+/// `throw`, `break`, `continue` or `stop` in the same block. Only the first
+/// such statement in each block is reported.
+pub fn unreachable_code(nodes: &[ASTNode]) -> Vec<String> {
+    let mut problems = Vec::new();
+    find_unreachable(nodes, None, &mut problems);
+    problems
+}
+
+fn find_unreachable(nodes: &[ASTNode], function: Option<&str>, problems: &mut Vec<String>) {
+    let mut exit = None;
+    for node in nodes {
+        // A stray `;` is an empty statement with nothing to run
+        if matches!(node, ASTNode::Program(statements) if statements.is_empty()) {
+            continue;
+        }
+        if let Some(keyword) = exit {
+            let location =
+                function.map(|name| format!(" in function '{}'", name)).unwrap_or_default();
+            problems.push(format!(
+                "code after '{}'{} is unreachable: {}",
+                keyword,
+                location,
+                statement_summary(node)
+            ));
+            return;
+        }
+        exit = match node {
+            ASTNode::ReturnStatement(_) => Some("return"),
+            ASTNode::ThrowStatement(_) => Some("throw"),
+            ASTNode::BreakStatement(_) => Some("break"),
+            ASTNode::ContinueStatement(_) => Some("continue"),
+            ASTNode::StopStatement => Some("stop"),
+            _ => None,
+        };
+        match node {
+            ASTNode::FunctionDeclaration { name, body, .. } => {
+                find_unreachable(body, Some(name), problems)
+            }
+            ASTNode::Program(statements) => find_unreachable(statements, function, problems),
+            ASTNode::IfStatement {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                find_unreachable(then_branch, function, problems);
+                if let Some(else_branch) = else_branch {
+                    find_unreachable(else_branch, function, problems);
+                }
+            }
+            ASTNode::GuardStatement { else_block: body, .. }
+            | ASTNode::DeferStatement(body)
+            | ASTNode::WhileStatement { body, .. }
+            | ASTNode::RepeatStatement { body, .. }
+            | ASTNode::ForeachStatement { body, .. } => find_unreachable(body, function, problems),
+            ASTNode::TryCatch {
+                try_block,
+                catch_block,
+                ..
+            } => {
+                find_unreachable(try_block, function, problems);
+                find_unreachable(catch_block, function, problems);
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Checker {
+    /// The names bound in each enclosing scope, outermost (the top level) first.
+    scopes: Vec<HashSet<Rc<str>>>,
+    /// The function whose body is being checked, for the report.
+    function: Option<Rc<str>>,
+    problems: Vec<String>,
+}
+
+impl Checker {
+    /// Checks a block, which is a scope of its own unless it is the top level.
+    fn check_block(&mut self, nodes: &[ASTNode], new_scope: bool) {
+        if new_scope {
+            self.scopes.push(block_bindings(nodes));
+        }
+        for node in nodes {
+            self.check_statement(node);
+        }
+        if new_scope {
+            self.scopes.pop();
+        }
+    }
+
+    fn check_scoped_block(&mut self, nodes: &[ASTNode], names: &[&Rc<str>]) {
+        let mut scope = block_bindings(nodes);
+        scope.extend(names.iter().map(|&name| name.clone()));
+        self.scopes.push(scope);
+        for node in nodes {
+            self.check_statement(node);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_statement(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Program(statements) => self.check_block(statements, false),
+            ASTNode::PrintStatement(expr)
+            | ASTNode::VariableAssignment(_, expr)
+            | ASTNode::LetDeclaration(_, expr)
+            | ASTNode::ThrowStatement(expr)
+            | ASTNode::ReturnStatement(Some(expr)) => self.check_expression(expr),
+            ASTNode::IfStatement {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.check_expression(condition);
+                self.check_block(then_branch, true);
+                if let Some(else_branch) = else_branch {
+                    self.check_block(else_branch, true);
+                }
+            }
+            ASTNode::GuardStatement { condition, else_block } => {
+                self.check_expression(condition);
+                self.check_block(else_block, true);
+            }
+            ASTNode::DeferStatement(body) => self.check_block(body, true),
+            ASTNode::WhileStatement { condition, body, .. }
+            | ASTNode::RepeatStatement { count: condition, body, .. } => {
+                self.check_expression(condition);
+                self.check_block(body, true);
+            }
+            ASTNode::ForeachStatement { index, variable, iterable, body, .. } => {
+                self.check_expression(iterable);
+                let mut names = vec![variable];
+                names.extend(index);
+                self.check_scoped_block(body, &names);
+            }
+            ASTNode::TryCatch {
+                try_block,
+                error_name,
+                catch_block,
+            } => {
+                self.check_block(try_block, true);
+                self.check_scoped_block(catch_block, &[error_name]);
+            }
+            ASTNode::FunctionDeclaration { name, parameters, body } => {
+                // A call sees only its own scope and the top level, not the
+                // blocks around the declaration.
+                let outer = self.scopes.split_off(1);
+                let enclosing = self.function.replace(name.clone());
+                self.check_scoped_block(body, &parameters.iter().collect::<Vec<_>>());
+                self.function = enclosing;
+                self.scopes.extend(outer);
+            }
+            ASTNode::ChainedAssignment { targets, value } => {
+                targets.iter().for_each(|target| self.check_target(target));
+                self.check_expression(value);
+            }
+            ASTNode::ParallelAssignment { targets, values } => {
+                targets.iter().for_each(|target| self.check_target(target));
+                values.iter().for_each(|value| self.check_expression(value));
+            }
+            ASTNode::IndexAssignment { target, index, value } => {
+                self.check_expression(target);
+                self.check_expression(index);
+                self.check_expression(value);
+            }
+            ASTNode::ReturnStatement(None)
+            | ASTNode::BreakStatement(_)
+            | ASTNode::ContinueStatement(_)
+            | ASTNode::StopStatement
+            | ASTNode::GlobalDeclaration(_)
+            | ASTNode::ImportStatement { .. } => {}
+            expr => self.check_expression(expr),
+        }
+    }
+
+    /// Assigning to a plain name binds it, but an indexed target reads it.
+    fn check_target(&mut self, target: &ASTNode) {
+        if !matches!(target, ASTNode::Identifier(_)) {
+            self.check_expression(target);
+        }
+    }
+
+    fn check_read(&mut self, name: &str) {
+        if self.scopes.iter().any(|scope| scope.contains(name)) {
+            return;
+        }
+        let problem = match self.function {
+            Some(ref function) => {
+                format!("'{}' is read in function '{}' but never assigned", name, function)
+            }
+            None => format!("'{}' is read but never assigned", name),
+        };
+        if !self.problems.contains(&problem) {
+            self.problems.push(problem);
+        }
+    }
+
+    fn check_expression(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Identifier(name) => self.check_read(name),
+            ASTNode::FunctionCall { arguments, .. }
+            | ASTNode::ArrayLiteral(arguments)
+            | ASTNode::SequenceExpression(arguments) => {
+                arguments.iter().for_each(|argument| self.check_expression(argument));
+            }
+            ASTNode::BinaryExpression { left, right, .. } => {
+                self.check_expression(left);
+                self.check_expression(right);
+            }
+            ASTNode::UnaryExpression { operand, .. } => self.check_expression(operand),
+            ASTNode::MapLiteral(entries) => {
+                for (key, value) in entries {
+                    self.check_expression(key);
+                    self.check_expression(value);
+                }
+            }
+            ASTNode::IndexExpression { target, index }
+            | ASTNode::OptionalIndexExpression { target, index } => {
+                self.check_expression(target);
+                self.check_expression(index);
+            }
+            ASTNode::SliceExpression { target, start, end } => {
+                self.check_expression(target);
+                for bound in [start, end].into_iter().flatten() {
+                    self.check_expression(bound);
+                }
+            }
+            ASTNode::MethodCall { receiver, arguments, .. } => {
+                self.check_expression(receiver);
+                arguments.iter().for_each(|argument| self.check_expression(argument));
+            }
+            ASTNode::CallExpression { callee, arguments } => {
+                self.check_expression(callee);
+                arguments.iter().for_each(|argument| self.check_expression(argument));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The names a block binds directly: its assignments and `let`s. Names bound
+/// inside nested blocks belong to those blocks.
+fn block_bindings(nodes: &[ASTNode]) -> HashSet<Rc<str>> {
+    let mut names = HashSet::new();
+    for node in nodes {
+        match node {
+            ASTNode::VariableAssignment(name, _) | ASTNode::LetDeclaration(name, _) => {
+                names.insert(name.clone());
+            }
+            ASTNode::ChainedAssignment { targets, .. }
+            | ASTNode::ParallelAssignment { targets, .. } => {
+                for target in targets {
+                    if let ASTNode::Identifier(name) = target {
+                        names.insert(name.clone());
+                    }
+                }
+            }
+            ASTNode::Program(statements) => names.extend(block_bindings(statements)),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Collects the names visible from every scope: top-level bindings, declared
+/// functions, import aliases and names a function declares `global`.
+fn collect_globals(nodes: &[ASTNode], nested: bool, globals: &mut HashSet<Rc<str>>) {
+    if !nested {
+        globals.extend(block_bindings(nodes));
+    }
+    for node in nodes {
+        match node {
+            ASTNode::FunctionDeclaration { name, body, .. } => {
+                globals.insert(name.clone());
+                collect_globals(body, true, globals);
+            }
+            ASTNode::ImportStatement { alias: Some(alias), .. } => {
+                globals.insert(alias.clone());
+            }
+            ASTNode::GlobalDeclaration(name) => {
+                globals.insert(name.clone());
+            }
+            ASTNode::Program(statements) => collect_globals(statements, nested, globals),
+            ASTNode::IfStatement {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                collect_globals(then_branch, true, globals);
+                if let Some(else_branch) = else_branch {
+                    collect_globals(else_branch, true, globals);
+                }
+            }
+            ASTNode::GuardStatement { else_block: body, .. }
+            | ASTNode::DeferStatement(body)
+            | ASTNode::WhileStatement { body, .. }
+            | ASTNode::RepeatStatement { body, .. }
+            | ASTNode::ForeachStatement { body, .. } => collect_globals(body, true, globals),
+            ASTNode::TryCatch {
+                try_block,
+                catch_block,
+                ..
+            } => {
+                collect_globals(try_block, true, globals);
+                collect_globals(catch_block, true, globals);
+            }
+            _ => {}
+        }
+    }
+}
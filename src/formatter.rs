@@ -0,0 +1,314 @@
+use crate::lexer::Token;
+use crate::parser::ASTNode;
+
+/// The indentation unit used when none is configured.
+pub const DEFAULT_INDENT: &str = "    ";
+
+/// Re-emits a parsed program as canonically formatted source, indenting
+/// each nesting level by `indent`. Comments are not part of the AST and are
+/// therefore dropped.
+pub fn format_program(nodes: &[ASTNode], indent: &str) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        format_statement(node, indent, 0, &mut out);
+    }
+    out
+}
+
+/// The first line of a statement's formatted source, such as `if (x > 1) {`.
+pub fn statement_summary(node: &ASTNode) -> String {
+    let mut out = String::new();
+    format_statement(node, DEFAULT_INDENT, 0, &mut out);
+    out.lines().next().unwrap_or_default().to_string()
+}
+
+fn format_statement(node: &ASTNode, unit: &str, depth: usize, out: &mut String) {
+    let indent = unit.repeat(depth);
+    match node {
+        ASTNode::Program(statements) => {
+            // Empty statements (a lone ';') carry no behavior and are dropped
+            for stmt in statements {
+                format_statement(stmt, unit, depth, out);
+            }
+        }
+        ASTNode::PrintStatement(expr) => {
+            out.push_str(&format!("{}print {};\n", indent, format_expression(expr)));
+        }
+        ASTNode::VariableAssignment(name, expr) => {
+            out.push_str(&format!("{}{} = {};\n", indent, name, format_expression(expr)));
+        }
+        ASTNode::LetDeclaration(name, expr) => {
+            out.push_str(&format!("{}let {} = {};\n", indent, name, format_expression(expr)));
+        }
+        ASTNode::ChainedAssignment { targets, value } => {
+            let targets: Vec<String> = targets.iter().map(format_expression).collect();
+            out.push_str(&format!(
+                "{}{} = {};\n",
+                indent,
+                targets.join(" = "),
+                format_expression(value)
+            ));
+        }
+        ASTNode::ParallelAssignment { targets, values } => {
+            out.push_str(&format!(
+                "{}{} = {};\n",
+                indent,
+                format_list(targets),
+                format_list(values)
+            ));
+        }
+        ASTNode::ImportStatement { path, alias } => {
+            let alias = alias.as_ref().map(|alias| format!(" as {}", alias)).unwrap_or_default();
+            out.push_str(&format!("{}import \"{}\"{};\n", indent, escape_string(path), alias));
+        }
+        ASTNode::GlobalDeclaration(name) => {
+            out.push_str(&format!("{}global {};\n", indent, name));
+        }
+        ASTNode::IndexAssignment { target, index, value } => {
+            out.push_str(&format!(
+                "{}{}[{}] = {};\n",
+                indent,
+                format_operand(target, u8::MAX),
+                format_expression(index),
+                format_expression(value)
+            ));
+        }
+        ASTNode::IfStatement {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            out.push_str(&format!("{}if ({}) {{\n", indent, format_expression(condition)));
+            format_block(then_branch, unit, depth + 1, out);
+            if let Some(else_branch) = else_branch {
+                out.push_str(&format!("{}}} else {{\n", indent));
+                format_block(else_branch, unit, depth + 1, out);
+            }
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::GuardStatement { condition, else_block } => {
+            out.push_str(&format!("{}guard {} else {{\n", indent, format_expression(condition)));
+            format_block(else_block, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::DeferStatement(body) => {
+            out.push_str(&format!("{}defer {{\n", indent));
+            format_block(body, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::WhileStatement { label, condition, body } => {
+            out.push_str(&format!(
+                "{}{}while ({}) {{\n",
+                indent,
+                format_label(label),
+                format_expression(condition)
+            ));
+            format_block(body, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::RepeatStatement { label, count, body } => {
+            out.push_str(&format!(
+                "{}{}repeat ({}) {{\n",
+                indent,
+                format_label(label),
+                format_expression(count)
+            ));
+            format_block(body, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::ForeachStatement { label, index, variable, iterable, body } => {
+            let index = index.as_ref().map(|index| format!("{}, ", index)).unwrap_or_default();
+            out.push_str(&format!(
+                "{}{}foreach ({}{} in {}) {{\n",
+                indent,
+                format_label(label),
+                index,
+                variable,
+                format_expression(iterable)
+            ));
+            format_block(body, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::TryCatch {
+            try_block,
+            error_name,
+            catch_block,
+        } => {
+            out.push_str(&format!("{}try {{\n", indent));
+            format_block(try_block, unit, depth + 1, out);
+            out.push_str(&format!("{}}} catch ({}) {{\n", indent, error_name));
+            format_block(catch_block, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        ASTNode::ThrowStatement(expr) => {
+            out.push_str(&format!("{}throw {};\n", indent, format_expression(expr)));
+        }
+        ASTNode::ReturnStatement(Some(expr)) => {
+            out.push_str(&format!("{}return {};\n", indent, format_expression(expr)));
+        }
+        ASTNode::ReturnStatement(None) => {
+            out.push_str(&format!("{}return;\n", indent));
+        }
+        ASTNode::StopStatement => {
+            out.push_str(&format!("{}stop;\n", indent));
+        }
+        ASTNode::BreakStatement(label) => {
+            out.push_str(&format!("{}break{};\n", indent, format_jump_label(label)));
+        }
+        ASTNode::ContinueStatement(label) => {
+            out.push_str(&format!("{}continue{};\n", indent, format_jump_label(label)));
+        }
+        ASTNode::FunctionDeclaration { name, parameters, body } => {
+            out.push_str(&format!("{}func {}({}) {{\n", indent, name, parameters.join(", ")));
+            format_block(body, unit, depth + 1, out);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        expr => {
+            out.push_str(&format!("{}{};\n", indent, format_expression(expr)));
+        }
+    }
+}
+
+fn format_block(statements: &[ASTNode], unit: &str, depth: usize, out: &mut String) {
+    for stmt in statements {
+        format_statement(stmt, unit, depth, out);
+    }
+}
+
+fn format_label(label: &Option<std::rc::Rc<str>>) -> String {
+    label.as_ref().map(|label| format!("{}: ", label)).unwrap_or_default()
+}
+
+fn format_jump_label(label: &Option<std::rc::Rc<str>>) -> String {
+    label.as_ref().map(|label| format!(" {}", label)).unwrap_or_default()
+}
+
+fn format_expression(node: &ASTNode) -> String {
+    match node {
+        ASTNode::NumberLiteral(n) => n.to_string(),
+        ASTNode::StringLiteral(s) => format!("\"{}\"", escape_string(s)),
+        ASTNode::PrefixedString { tag, value } => format!("{}\"{}\"", tag, escape_string(value)),
+        ASTNode::BooleanLiteral(b) => b.to_string(),
+        ASTNode::NilLiteral => "nil".to_string(),
+        ASTNode::Identifier(name) => name.to_string(),
+        ASTNode::FunctionCall { name, arguments } => {
+            format!("{}({})", name, format_list(arguments))
+        }
+        ASTNode::BinaryExpression { left, operator, right } => {
+            let precedence = precedence(operator);
+            // Operators are left-associative, so an equal-precedence right
+            // operand needs parentheses to keep its grouping.
+            format!(
+                "{} {} {}",
+                format_operand(left, precedence),
+                operator_symbol(operator),
+                format_operand(right, precedence + 1)
+            )
+        }
+        ASTNode::UnaryExpression { operator, operand } => {
+            format!("{}{}", operator_symbol(operator), format_operand(operand, u8::MAX))
+        }
+        ASTNode::ArrayLiteral(elements) => format!("[{}]", format_list(elements)),
+        ASTNode::MapLiteral(entries) => {
+            let parts: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| {
+                    format!("{}: {}", format_expression(key), format_expression(value))
+                })
+                .collect();
+            format!("{{{}}}", parts.join(", "))
+        }
+        ASTNode::IndexExpression { target, index } => {
+            format!("{}[{}]", format_operand(target, u8::MAX), format_expression(index))
+        }
+        ASTNode::OptionalIndexExpression { target, index } => {
+            format!("{}?[{}]", format_operand(target, u8::MAX), format_expression(index))
+        }
+        ASTNode::SliceExpression { target, start, end } => {
+            let bound = |bound: &Option<Box<ASTNode>>| {
+                bound.as_deref().map(format_expression).unwrap_or_default()
+            };
+            format!("{}[{}:{}]", format_operand(target, u8::MAX), bound(start), bound(end))
+        }
+        ASTNode::MethodCall { receiver, method, arguments } => {
+            format!("{}.{}({})", format_operand(receiver, u8::MAX), method, format_list(arguments))
+        }
+        ASTNode::SequenceExpression(expressions) => format!("({})", format_list(expressions)),
+        ASTNode::CallExpression { callee, arguments } => {
+            format!("{}({})", format_operand(callee, u8::MAX), format_list(arguments))
+        }
+        _ => String::new(),
+    }
+}
+
+/// Formats a sub-expression, parenthesizing it if it binds more loosely
+/// than `min_precedence`.
+fn format_operand(node: &ASTNode, min_precedence: u8) -> String {
+    match node {
+        ASTNode::BinaryExpression { operator, .. } if precedence(operator) < min_precedence => {
+            format!("({})", format_expression(node))
+        }
+        ASTNode::UnaryExpression { .. } if min_precedence == u8::MAX => {
+            format!("({})", format_expression(node))
+        }
+        _ => format_expression(node),
+    }
+}
+
+/// Escapes a string using the escape sequences the lexer understands.
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_list(nodes: &[ASTNode]) -> String {
+    nodes.iter().map(format_expression).collect::<Vec<_>>().join(", ")
+}
+
+fn precedence(operator: &Token) -> u8 {
+    match operator {
+        Token::QuestionQuestion => 1,
+        Token::CaretCaret => 2,
+        Token::EqualEqual | Token::NotEqual => 3,
+        Token::LessThan
+        | Token::GreaterThan
+        | Token::LessEqual
+        | Token::GreaterEqual
+        | Token::In => 4,
+        Token::Plus | Token::Minus => 5,
+        Token::Star | Token::Slash => 6,
+        _ => 0,
+    }
+}
+
+pub fn operator_symbol(operator: &Token) -> &'static str {
+    match operator {
+        Token::Plus => "+",
+        Token::Minus => "-",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::EqualEqual => "==",
+        Token::NotEqual => "!=",
+        Token::LessThan => "<",
+        Token::GreaterThan => ">",
+        Token::LessEqual => "<=",
+        Token::GreaterEqual => ">=",
+        Token::Bang => "!",
+        Token::In => "in",
+        Token::QuestionQuestion => "??",
+        Token::CaretCaret => "^^",
+        _ => "?",
+    }
+}
@@ -0,0 +1,356 @@
+use crate::parser::ASTNode;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Parses a JSON document into juul values: objects become maps, arrays
+/// become arrays and `null` becomes nil. Errors carry the character
+/// position where parsing failed.
+pub fn parse_json(input: &str) -> Result<ASTNode, String> {
+    let mut parser = JsonParser {
+        chars: input.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Serializes a juul value as compact JSON. Map keys are sorted so the
+/// output is deterministic; functions and non-finite numbers cannot be
+/// represented and are an error, as is a collection that contains itself.
+pub fn to_json(value: &ASTNode) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(value, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+/// Writes a value, given the collections it is nested in.
+fn write_value(value: &ASTNode, open: &mut Vec<*const ()>, out: &mut String) -> Result<(), String> {
+    let ptr = match value {
+        ASTNode::Array(elements) => Some(Rc::as_ptr(elements).cast()),
+        ASTNode::Map(entries) => Some(Rc::as_ptr(entries).cast()),
+        _ => None,
+    };
+    if let Some(ptr) = ptr {
+        if open.contains(&ptr) {
+            return Err("Cannot serialize a value that contains itself to JSON".into());
+        }
+        open.push(ptr);
+    }
+    match value {
+        ASTNode::NilLiteral => out.push_str("null"),
+        ASTNode::BooleanLiteral(b) => out.push_str(&b.to_string()),
+        ASTNode::NumberLiteral(n) if n.is_finite() => out.push_str(&n.to_string()),
+        ASTNode::NumberLiteral(n) => {
+            return Err(format!("Cannot serialize {} to JSON", n));
+        }
+        ASTNode::StringLiteral(s) => write_string(s, out),
+        ASTNode::Array(elements) => {
+            out.push('[');
+            for (i, element) in elements.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(element, open, out)?;
+            }
+            out.push(']');
+        }
+        ASTNode::Map(entries) => {
+            let entries = entries.borrow();
+            let mut keys: Vec<&String> = entries.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(key, out);
+                out.push(':');
+                write_value(&entries[key], open, out)?;
+            }
+            out.push('}');
+        }
+        ASTNode::FunctionDeclaration { name, .. } => {
+            return Err(format!("Cannot serialize function '{}' to JSON", name));
+        }
+        ASTNode::Memoized { function, .. } => return write_value(function, open, out),
+        _ => return Err("Cannot serialize value to JSON".into()),
+    }
+    if ptr.is_some() {
+        open.pop();
+    }
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// How deeply arrays and objects may nest. Parsing recurses once per level,
+/// so unbounded nesting would overflow the stack on adversarial input.
+const MAX_DEPTH: usize = 200;
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+    /// How many arrays and objects enclose the current position.
+    depth: usize,
+}
+
+impl JsonParser {
+    fn error(&self, message: &str) -> String {
+        format!("Invalid JSON at position {}: {}", self.pos, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<ASTNode, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{' | '[') if self.depth == MAX_DEPTH => Err(self.error("nested too deeply")),
+            Some(c @ ('{' | '[')) => {
+                self.depth += 1;
+                let value = if c == '{' { self.parse_object() } else { self.parse_array() };
+                self.depth -= 1;
+                value
+            }
+            Some('"') => Ok(ASTNode::StringLiteral(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some('t') => self.parse_keyword("true", ASTNode::BooleanLiteral(true)),
+            Some('f') => self.parse_keyword("false", ASTNode::BooleanLiteral(false)),
+            Some('n') => self.parse_keyword("null", ASTNode::NilLiteral),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_keyword(&mut self, keyword: &str, value: ASTNode) -> Result<ASTNode, String> {
+        for expected in keyword.chars() {
+            if self.peek() != Some(expected) {
+                return Err(self.error(&format!("expected '{}'", keyword)));
+            }
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn parse_object(&mut self) -> Result<ASTNode, String> {
+        self.pos += 1; // Consume '{'
+        let mut entries = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(ASTNode::Map(Rc::new(RefCell::new(entries))));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            entries.insert(key, value);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                Some(_) => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or '}'"));
+                }
+                None => return Err(self.error("unexpected end of input")),
+            }
+        }
+        Ok(ASTNode::Map(Rc::new(RefCell::new(entries))))
+    }
+
+    fn parse_array(&mut self) -> Result<ASTNode, String> {
+        self.pos += 1; // Consume '['
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(ASTNode::Array(Rc::new(RefCell::new(elements))));
+        }
+        loop {
+            elements.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => break,
+                Some(_) => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or ']'"));
+                }
+                None => return Err(self.error("unexpected end of input")),
+            }
+        }
+        Ok(ASTNode::Array(Rc::new(RefCell::new(elements))))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.pos += 1; // Consume '"'
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.parse_unicode_escape()?),
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"));
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Decodes the digits of a `\u` escape, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+        if self.next() != Some('\\') || self.next() != Some('u') {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<ASTNode, String> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        if !self.consume_digits() {
+            return Err(self.error("expected a digit"));
+        }
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            if !self.consume_digits() {
+                return Err(self.error("expected a digit after '.'"));
+            }
+        }
+        if let Some('e' | 'E') = self.peek() {
+            self.pos += 1;
+            if let Some('+' | '-') = self.peek() {
+                self.pos += 1;
+            }
+            if !self.consume_digits() {
+                return Err(self.error("expected a digit in exponent"));
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse()
+            .map(ASTNode::NumberLiteral)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    /// Consumes a run of ASCII digits, returning whether there was any.
+    fn consume_digits(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deeply_nested_input_is_an_error() {
+        let input = "[".repeat(100_000);
+        assert_eq!(
+            parse_json(&input).unwrap_err(),
+            "Invalid JSON at position 200: nested too deeply"
+        );
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_is_accepted() {
+        let input = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse_json(&input).is_ok());
+        let input = format!(
+            "{}{{}}{}",
+            "{\"a\":".repeat(MAX_DEPTH - 1),
+            "}".repeat(MAX_DEPTH - 1)
+        );
+        assert!(parse_json(&input).is_ok());
+    }
+}
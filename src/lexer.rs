@@ -1,14 +1,47 @@
+use std::collections::HashSet;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::str::Chars;
 
+/// A line and column in the source, both counted from 1. Columns count
+/// characters rather than bytes, so they match what an editor shows.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Print,
     If,
     Else,
     Function,
-    Identifier(String),
+    Return,
+    True,
+    False,
+    In,
+    Try,
+    Catch,
+    Throw,
+    Let,
+    Global,
+    Import,
+    Foreach,
+    While,
+    Repeat,
+    Guard,
+    Defer,
+    Break,
+    Continue,
+    Stop,
+    Nil,
+    Identifier(Rc<str>),
     StringLiteral(String),
+    /// A string with a tag written directly before its opening quote, such
+    /// as `json"[1, 2]"`. Raw strings (`r"..."`) are lexed separately.
+    PrefixedString(Rc<str>, String),
     NumberLiteral(f64),
     Assign,
     Plus,
@@ -21,6 +54,10 @@ pub enum Token {
     GreaterThan,
     LessEqual,
     GreaterEqual,
+    Question,
+    QuestionQuestion,
+    CaretCaret,
+    Pipe,
     Bang,
     Comma,
     Semicolon,
@@ -28,38 +65,83 @@ pub enum Token {
     RightParen,
     LeftBrace,
     RightBrace,
-    EOF, 
+    LeftBracket,
+    RightBracket,
+    Colon,
+    Dot,
+    EOF,
 }
 
-pub fn lex(input: &str) -> Vec<Token> {
+/// Splits the source into tokens, returning alongside them the position
+/// where each token starts. Errors name the position where lexing stopped.
+pub fn lex(input: &str) -> Result<(Vec<Token>, Vec<Position>), String> {
+    let mut chars = Cursor::new(input);
+    lex_tokens(&mut chars).map_err(|message| {
+        format!("Line {}, column {}: {}", chars.position.line, chars.position.col, message)
+    })
+}
+
+fn lex_tokens(chars: &mut Cursor) -> Result<(Vec<Token>, Vec<Position>), String> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+    let mut positions = Vec::new();
+    let mut names = HashSet::new();
 
     while let Some(&ch) = chars.peek() {
+        let start = chars.position;
+        let token_count = tokens.len();
         match ch {
             c if c.is_whitespace() => {
                 chars.next(); // Skip whitespace
             }
+            'r' if starts_raw_string(chars) => {
+                // Raw string literals
+                chars.next(); // Consume 'r'
+                let string_lit = collect_raw_string(chars)?;
+                tokens.push(Token::StringLiteral(string_lit));
+            }
             c if c.is_alphabetic() || c == '_' => {
                 // Identifiers and keywords
-                let ident = collect_identifier(&mut chars);
+                let ident = collect_identifier(chars);
                 match ident.as_str() {
                     "print" => tokens.push(Token::Print),
                     "if" => tokens.push(Token::If),
                     "else" => tokens.push(Token::Else),
                     "func" => tokens.push(Token::Function),
-                    _ => tokens.push(Token::Identifier(ident)),
+                    "return" => tokens.push(Token::Return),
+                    "true" => tokens.push(Token::True),
+                    "false" => tokens.push(Token::False),
+                    "in" => tokens.push(Token::In),
+                    "try" => tokens.push(Token::Try),
+                    "catch" => tokens.push(Token::Catch),
+                    "throw" => tokens.push(Token::Throw),
+                    "let" => tokens.push(Token::Let),
+                    "nil" => tokens.push(Token::Nil),
+                    "global" => tokens.push(Token::Global),
+                    "import" => tokens.push(Token::Import),
+                    "foreach" => tokens.push(Token::Foreach),
+                    "while" => tokens.push(Token::While),
+                    "repeat" => tokens.push(Token::Repeat),
+                    "guard" => tokens.push(Token::Guard),
+                    "defer" => tokens.push(Token::Defer),
+                    "break" => tokens.push(Token::Break),
+                    "continue" => tokens.push(Token::Continue),
+                    "stop" => tokens.push(Token::Stop),
+                    _ if chars.next_if_eq(&'"').is_some() => {
+                        let string_lit = collect_string_literal(chars)?;
+                        tokens.push(Token::PrefixedString(ident.into(), string_lit));
+                    }
+                    _ => tokens.push(Token::Identifier(intern(&mut names, ident))),
                 }
             }
-            c if c.is_digit(10) => {
+            c if c.is_ascii_digit() => {
                 // Numbers
-                let number = collect_number(&mut chars);
+                let number = collect_number(chars);
                 tokens.push(Token::NumberLiteral(number));
             }
             '"' => {
                 // String literals
                 chars.next(); // Consume '"'
-                let string_lit = collect_string_literal(&mut chars);
+                let string_lit = collect_string_literal(chars)?;
                 tokens.push(Token::StringLiteral(string_lit));
             }
             '=' => {
@@ -98,6 +180,29 @@ pub fn lex(input: &str) -> Vec<Token> {
                     tokens.push(Token::GreaterThan);
                 }
             }
+            '?' => {
+                chars.next(); // Consume '?'
+                if let Some('?') = chars.peek() {
+                    chars.next();
+                    tokens.push(Token::QuestionQuestion);
+                } else {
+                    tokens.push(Token::Question);
+                }
+            }
+            '^' => {
+                chars.next(); // Consume '^'
+                // A lone '^' is skipped like any other unknown character
+                if chars.next_if_eq(&'^').is_some() {
+                    tokens.push(Token::CaretCaret);
+                }
+            }
+            '|' => {
+                chars.next(); // Consume '|'
+                if let Some('>') = chars.peek() {
+                    chars.next();
+                    tokens.push(Token::Pipe);
+                }
+            }
             '+' => {
                 chars.next();
                 tokens.push(Token::Plus);
@@ -111,8 +216,18 @@ pub fn lex(input: &str) -> Vec<Token> {
                 tokens.push(Token::Star);
             }
             '/' => {
-                chars.next();
-                tokens.push(Token::Slash);
+                chars.next(); // Consume '/'
+                if let Some('/') = chars.peek() {
+                    // Line comment: skip to the end of the line
+                    while let Some(&c) = chars.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        chars.next();
+                    }
+                } else {
+                    tokens.push(Token::Slash);
+                }
             }
             ',' => {
                 chars.next();
@@ -138,19 +253,94 @@ pub fn lex(input: &str) -> Vec<Token> {
                 chars.next();
                 tokens.push(Token::RightBrace);
             }
+            '[' => {
+                chars.next();
+                tokens.push(Token::LeftBracket);
+            }
+            ']' => {
+                chars.next();
+                tokens.push(Token::RightBracket);
+            }
+            ':' => {
+                chars.next();
+                tokens.push(Token::Colon);
+            }
+            '.' => {
+                chars.next();
+                tokens.push(Token::Dot);
+            }
             _ => {
                 // Unknown character
                 chars.next(); // Consume the character to prevent infinite loop
                 // Optionally, handle or report an error here
             }
         }
+        // Whitespace and comments produce no token
+        if tokens.len() > token_count {
+            positions.push(start);
+        }
     }
 
     tokens.push(Token::EOF); // Add EOF token at the end
-    tokens
+    positions.push(chars.position);
+    Ok((tokens, positions))
+}
+
+/// The characters of the source, tracking the position of the next one.
+#[derive(Clone)]
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor {
+            chars: input.chars().peekable(),
+            position: Position { line: 1, col: 1 },
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        if self.peek() == Some(expected) {
+            self.next()
+        } else {
+            None
+        }
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.col = 1;
+        } else {
+            self.position.col += 1;
+        }
+        Some(c)
+    }
 }
 
-fn collect_identifier(chars: &mut Peekable<Chars>) -> String {
+/// Returns the shared copy of `ident`, so every occurrence of a name in the
+/// source points at the same allocation and cloning it never copies the text.
+fn intern(names: &mut HashSet<Rc<str>>, ident: String) -> Rc<str> {
+    if let Some(name) = names.get(ident.as_str()) {
+        return name.clone();
+    }
+    let name: Rc<str> = Rc::from(ident);
+    names.insert(name.clone());
+    name
+}
+
+fn collect_identifier(chars: &mut Cursor) -> String {
     let mut ident = String::new();
     while let Some(&c) = chars.peek() {
         if c.is_alphanumeric() || c == '_' {
@@ -163,23 +353,64 @@ fn collect_identifier(chars: &mut Peekable<Chars>) -> String {
     ident
 }
 
-fn collect_number(chars: &mut Peekable<Chars>) -> f64 {
+fn collect_number(chars: &mut Cursor) -> f64 {
     let mut num_str = String::new();
     while let Some(&c) = chars.peek() {
-        if c.is_digit(10) || c == '.' {
+        if c.is_ascii_digit() || c == '.' {
             num_str.push(c);
             chars.next();
         } else {
             break;
         }
     }
+    // An exponent such as `1e308` or `2.5E-3`, only if digits follow the `e`
+    if let Some('e' | 'E') = chars.peek() {
+        let mut ahead = chars.clone();
+        ahead.next();
+        if let Some('+' | '-') = ahead.peek() {
+            ahead.next();
+        }
+        if ahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+            num_str.push('e');
+            chars.next(); // Consume 'e'
+            if let Some(&sign @ ('+' | '-')) = chars.peek() {
+                num_str.push(sign);
+                chars.next();
+            }
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() {
+                    num_str.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        }
+    }
     num_str.parse::<f64>().unwrap_or(0.0) // Handle parse errors appropriately
 }
 
-fn collect_string_literal(chars: &mut Peekable<Chars>) -> String {
+fn collect_string_literal(chars: &mut Cursor) -> Result<String, String> {
     let mut string_lit = String::new();
     while let Some(&c) = chars.peek() {
-        if c != '"' {
+        if c == '\\' {
+            chars.next(); // Consume '\\'
+            match chars.next() {
+                Some('n') => string_lit.push('\n'),
+                Some('t') => string_lit.push('\t'),
+                Some('r') => string_lit.push('\r'),
+                Some('0') => string_lit.push('\0'),
+                Some('\\') => string_lit.push('\\'),
+                Some('"') => string_lit.push('"'),
+                Some('x') => string_lit.push(collect_hex_escape(chars)?),
+                Some(other) => {
+                    // Unknown escape: keep it verbatim
+                    string_lit.push('\\');
+                    string_lit.push(other);
+                }
+                None => string_lit.push('\\'),
+            }
+        } else if c != '"' {
             string_lit.push(c);
             chars.next();
         } else {
@@ -187,5 +418,50 @@ fn collect_string_literal(chars: &mut Peekable<Chars>) -> String {
             break;
         }
     }
-    string_lit
+    Ok(string_lit)
+}
+
+/// Whether the input continues with `r"` or `r#...#"`, the start of a raw
+/// string rather than an identifier beginning with `r`.
+fn starts_raw_string(chars: &Cursor) -> bool {
+    let mut lookahead = chars.clone().skip(1).skip_while(|&c| c == '#');
+    lookahead.next() == Some('"')
+}
+
+/// Reads a raw string after its `r` prefix. Backslashes are kept as
+/// written, and the literal only ends at a `"` followed by as many `#` as
+/// opened it, so `r#"say "hi""#` can contain quotes.
+fn collect_raw_string(chars: &mut Cursor) -> Result<String, String> {
+    let mut hashes = 0;
+    while chars.next_if_eq(&'#').is_some() {
+        hashes += 1;
+    }
+    chars.next(); // Consume opening '"'
+    let mut string_lit = String::new();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            let mut closing = chars.clone();
+            if (0..hashes).all(|_| closing.next() == Some('#')) {
+                *chars = closing;
+                return Ok(string_lit);
+            }
+        }
+        string_lit.push(c);
+    }
+    Err("Unterminated raw string literal.".into())
+}
+
+/// Reads the two hex digits of a `\xNN` escape as a code point up to 0xFF.
+fn collect_hex_escape(chars: &mut Cursor) -> Result<char, String> {
+    let mut code = 0;
+    for _ in 0..2 {
+        match chars.peek().and_then(|c| c.to_digit(16)) {
+            Some(digit) => {
+                chars.next();
+                code = code * 16 + digit;
+            }
+            None => return Err("Invalid '\\x' escape: expected two hex digits.".into()),
+        }
+    }
+    Ok(char::from(code as u8))
 }
@@ -1,80 +1,346 @@
+mod checker;
+mod formatter;
+mod json;
 mod lexer;
+mod optimizer;
 mod parser;
 
 use lexer::lex;
 use lexer::Token;
-use parser::{parse, ASTNode};
-use std::collections::HashMap;
+use parser::{count_nodes, parse, parse_eval, ASTNode};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fmt;
 use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: juul <source_file>");
-        return;
+    let mut format_only = false;
+    let mut check_only = false;
+    let mut show_stats = false;
+    let mut profile = false;
+    let mut max_iterations = None;
+    let mut trace = false;
+    let mut strict = false;
+    let mut indent = formatter::DEFAULT_INDENT.to_string();
+    let mut filename = None;
+    let mut flags = args[1..].iter();
+    while let Some(arg) = flags.next() {
+        match arg.as_str() {
+            "--format" => format_only = true,
+            "--check" => check_only = true,
+            "--stats" => show_stats = true,
+            "--profile" => profile = true,
+            "--trace" => trace = true,
+            "--strict" => strict = true,
+            "--max-iters" => {
+                let Some(limit) = flags.next().and_then(|limit| limit.parse().ok()) else {
+                    eprintln!("Error: --max-iters expects a number of iterations");
+                    process::exit(1);
+                };
+                max_iterations = Some(limit);
+            }
+            "--indent" => {
+                // A number of spaces, or `tab` to indent with tabs
+                let unit = match flags.next().map(String::as_str) {
+                    Some("tab") => Some("\t".to_string()),
+                    Some(width) => width.parse().ok().map(|width| " ".repeat(width)),
+                    None => None,
+                };
+                let Some(unit) = unit else {
+                    eprintln!("Error: --indent expects a number of spaces or 'tab'");
+                    process::exit(1);
+                };
+                indent = unit;
+            }
+            _ => filename = Some(arg),
+        }
     }
 
-    let filename = &args[1];
+    let Some(filename) = filename else {
+        eprintln!(
+            "Usage: juul [--format] [--check] [--indent <spaces|tab>] [--stats] [--profile] \
+             [--trace] [--strict] [--max-iters <n>] <source_file>"
+        );
+        return;
+    };
     let code = fs::read_to_string(filename).expect("Could not read file");
 
-    let tokens = lex(&code);
+    let (tokens, positions) = match lex(&code) {
+        Ok(lexed) => lexed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
     // Uncomment the following line to debug tokens
     // println!("{:?}", tokens);
 
-    match parse(&tokens) {
-        Ok(ast_nodes) => {
-            interpret(ast_nodes);
+    let result = parse(&tokens, &positions).map_err(|e| e.to_string()).and_then(|ast_nodes| {
+        if show_stats {
+            eprintln!("Tokens: {}", tokens.len());
+            eprintln!("AST nodes: {}", count_nodes(&ast_nodes));
         }
-        Err(e) => {
-            eprintln!("Error: {}", e);
+        if format_only {
+            print!("{}", formatter::format_program(&ast_nodes, &indent));
+            Ok(())
+        } else if check_only {
+            let problems = checker::check_program(&ast_nodes);
+            for problem in &problems {
+                eprintln!("Warning: {}", problem);
+            }
+            match problems.len() {
+                0 => Ok(()),
+                1 => Err("1 problem found".to_string()),
+                count => Err(format!("{} problems found", count)),
+            }
+        } else {
+            // Unreachable code is likely a mistake, but only fatal under --strict
+            for problem in checker::unreachable_code(&ast_nodes) {
+                if strict {
+                    return Err(problem);
+                }
+                eprintln!("Warning: {}", problem);
+            }
+            let script_path = fs::canonicalize(filename).ok();
+            let options = RunOptions {
+                profile,
+                trace,
+                strict,
+                max_iterations,
+            };
+            interpret(optimizer::fold_constants(ast_nodes), script_path, options)
         }
+    });
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Interpreter settings chosen on the command line.
+struct RunOptions {
+    profile: bool,
+    trace: bool,
+    strict: bool,
+    max_iterations: Option<u64>,
+}
+
+fn interpret(
+    ast_nodes: Vec<ASTNode>,
+    script_path: Option<PathBuf>,
+    options: RunOptions,
+) -> Result<(), String> {
+    let mut interp = Interpreter::new();
+    interp.importing.extend(script_path.clone());
+    interp.script_path = script_path;
+    interp.max_iterations = options.max_iterations;
+    interp.strict = options.strict;
+    if options.trace {
+        interp.trace_depth = Some(0);
+    }
+    if options.profile {
+        interp.call_counts = Some(HashMap::new());
+    }
+    let global_env = interp.globals.clone();
+
+    let result = run_statements(&ast_nodes, &global_env, &mut interp);
+    if let Some(call_counts) = &interp.call_counts {
+        print_profile(call_counts);
+    }
+    result.map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Reports how often each user function was called, most called first.
+fn print_profile(call_counts: &HashMap<Rc<str>, usize>) {
+    let mut counts: Vec<(&Rc<str>, &usize)> = call_counts.iter().collect();
+    counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+    eprintln!("Profile:");
+    for (name, count) in counts {
+        eprintln!("  {}: {} call{}", name, count, if *count == 1 { "" } else { "s" });
     }
 }
 
-fn interpret(ast_nodes: Vec<ASTNode>) {
-    let mut global_env = Environment::new(None);
-    let mut functions = HashMap::new();
+/// Interpreter-wide state shared by every scope: the top-level scope,
+/// declared functions and options that scripts can toggle at runtime.
+struct Interpreter {
+    globals: Environment,
+    functions: HashMap<Rc<str>, ASTNode>,
+    thousands_separators: bool,
+    /// Whether arithmetic producing infinity or NaN is a runtime error.
+    numeric_guard: bool,
+    /// Absolute path of the running script, if it was loaded from a file.
+    /// While an import runs this is the imported file.
+    script_path: Option<PathBuf>,
+    /// Files whose import is in progress, outermost first, to detect cycles.
+    importing: Vec<PathBuf>,
+    /// Files imported with `as`, by namespace name.
+    namespaces: HashMap<Rc<str>, Namespace>,
+    /// The user function whose body is executing, for tail-call detection.
+    current_function: Option<Rc<str>>,
+    /// How many `try` blocks enclose the current point within this function.
+    try_depth: usize,
+    /// How many `defer` blocks are waiting to run within this function.
+    pending_defers: usize,
+    /// Calls per user function, collected only under `--profile`.
+    call_counts: Option<HashMap<Rc<str>, usize>>,
+    /// How many times a single loop may repeat before it is stopped, if
+    /// limited with `--max-iters`.
+    max_iterations: Option<u64>,
+    /// Under `--trace`, how many blocks enclose the statement being run.
+    trace_depth: Option<usize>,
+    /// Whether reading an undefined variable is a runtime error (`--strict`)
+    /// rather than a warning that evaluates to 0.
+    strict: bool,
+    /// Random for each run, so `uid()` values differ between runs too.
+    uid_seed: u64,
+    /// How many ids `uid()` has handed out, to keep them unique in a run.
+    uid_count: u64,
+}
 
-    for node in ast_nodes {
-        execute(node, &mut global_env, &mut functions);
+impl Interpreter {
+    fn new() -> Self {
+        Interpreter {
+            globals: global_scope(),
+            functions: HashMap::new(),
+            thousands_separators: false,
+            numeric_guard: true,
+            script_path: None,
+            importing: Vec::new(),
+            namespaces: HashMap::new(),
+            current_function: None,
+            try_depth: 0,
+            pending_defers: 0,
+            call_counts: None,
+            max_iterations: None,
+            trace_depth: None,
+            strict: false,
+            uid_seed: RandomState::new().build_hasher().finish(),
+            uid_count: 0,
+        }
     }
 }
 
+/// A fresh top-level scope holding the predefined constants `PI` and `E`.
+fn global_scope() -> Environment {
+    let env = Environment::new(None);
+    env.define("PI".into(), ASTNode::NumberLiteral(std::f64::consts::PI));
+    env.define("E".into(), ASTNode::NumberLiteral(std::f64::consts::E));
+    env
+}
+
+/// The functions and top-level scope of a file imported with `as`. Its
+/// functions run against these rather than the importer's, so they can call
+/// each other and read their own globals by plain name.
 #[derive(Clone)]
-struct Environment {
-    values: HashMap<String, ASTNode>,
-    enclosing: Option<Box<Environment>>,
+struct Namespace {
+    functions: HashMap<Rc<str>, ASTNode>,
+    globals: Environment,
+}
+
+/// A single lexical scope: its own bindings plus a link to the scope that
+/// encloses it.
+struct Scope {
+    values: HashMap<Rc<str>, ASTNode>,
+    enclosing: Option<Environment>,
+    /// Set on the scope of a function call. Assignments do not reach past it
+    /// into the top-level scope unless the name was declared `global`.
+    function: bool,
+    globals: HashSet<Rc<str>>,
 }
 
+/// A shared handle to a scope. Cloning the handle is cheap, so entering a
+/// block or calling a function links to the parent scope instead of copying
+/// it, and assignments to outer variables are visible once the block ends.
+#[derive(Clone)]
+struct Environment(Rc<RefCell<Scope>>);
+
 impl Environment {
-    fn new(enclosing: Option<Box<Environment>>) -> Self {
-        Environment {
+    fn new(enclosing: Option<Environment>) -> Self {
+        Environment(Rc::new(RefCell::new(Scope {
             values: HashMap::new(),
             enclosing,
+            function: false,
+            globals: HashSet::new(),
+        })))
+    }
+
+    /// The scope of a function call, which can read the top-level scope but
+    /// only assign to it through `global` declarations.
+    fn new_function(globals: Environment) -> Self {
+        let env = Environment::new(Some(globals));
+        env.0.borrow_mut().function = true;
+        env
+    }
+
+    /// Marks `name` as global in the innermost function scope. At the top
+    /// level every name is already global, so this does nothing.
+    fn declare_global(&self, name: Rc<str>) {
+        let mut scope = self.0.borrow_mut();
+        if scope.function {
+            scope.globals.insert(name);
+        } else if let Some(ref enclosing) = scope.enclosing {
+            enclosing.declare_global(name);
         }
     }
 
-    fn define(&mut self, name: String, value: ASTNode) {
-        self.values.insert(name, value);
+    fn define(&self, name: Rc<str>, value: ASTNode) {
+        self.0.borrow_mut().values.insert(name, value);
     }
 
     fn get(&self, name: &str) -> Option<ASTNode> {
-        if let Some(value) = self.values.get(name) {
+        let scope = self.0.borrow();
+        if let Some(value) = scope.values.get(name) {
             Some(value.clone())
-        } else if let Some(ref enclosing) = self.enclosing {
+        } else if let Some(ref enclosing) = scope.enclosing {
             enclosing.get(name)
         } else {
             None
         }
     }
 
-    fn assign(&mut self, name: &str, value: ASTNode) -> bool {
-        if self.values.contains_key(name) {
-            self.values.insert(name.to_string(), value);
+    /// Every visible binding, sorted by name. Inner scopes shadow outer ones.
+    fn bindings(&self) -> Vec<(Rc<str>, ASTNode)> {
+        let scope = self.0.borrow();
+        let mut bindings = match scope.enclosing {
+            Some(ref enclosing) => enclosing.bindings(),
+            None => Vec::new(),
+        };
+        for (name, value) in &scope.values {
+            bindings.retain(|(existing, _)| existing != name);
+            bindings.push((name.clone(), value.clone()));
+        }
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        bindings
+    }
+
+    fn assign(&self, name: &str, value: ASTNode) -> bool {
+        let mut scope = self.0.borrow_mut();
+        if let Some(slot) = scope.values.get_mut(name) {
+            *slot = value;
+            true
+        } else if scope.function {
+            if !scope.globals.contains(name) {
+                return false;
+            }
+            if let Some(ref globals) = scope.enclosing {
+                if !globals.assign(name, value.clone()) {
+                    globals.define(name.into(), value);
+                }
+            }
             true
-        } else if let Some(ref mut enclosing) = self.enclosing {
+        } else if let Some(ref enclosing) = scope.enclosing {
             enclosing.assign(name, value)
         } else {
             false
@@ -82,177 +348,1875 @@ impl Environment {
     }
 }
 
-fn execute(node: ASTNode, env: &mut Environment, functions: &mut HashMap<String, ASTNode>) {
+/// A runtime failure: either an error raised by the interpreter or a value
+/// raised by a `throw` statement. Both can be intercepted by `try`/`catch`.
+enum RuntimeError {
+    Message(String),
+    Thrown(ASTNode),
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError::Message(message)
+    }
+}
+
+impl From<&str> for RuntimeError {
+    fn from(message: &str) -> Self {
+        RuntimeError::Message(message.to_string())
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Message(message) => write!(f, "{}", message),
+            RuntimeError::Thrown(value) => {
+                write!(f, "Uncaught exception: {}", format_element(value))
+            }
+        }
+    }
+}
+
+/// The outcome of executing a statement: either fall through to the next
+/// statement or unwind to the enclosing function call with a value.
+/// `TailCall` carries the arguments of a `return f(...)` where `f` is the
+/// function currently executing, so the call can loop instead of recursing.
+enum ControlFlow {
+    Normal,
+    Return(ASTNode),
+    TailCall(Vec<ASTNode>),
+    Break(Option<Rc<str>>),
+    Continue(Option<Rc<str>>),
+    /// Ends the program (or the imported file) early without an error.
+    Stop,
+}
+
+/// How a loop carries on after its body finishes with a given control flow.
+enum LoopStep {
+    Next,
+    Exit,
+    /// Leave the loop, passing the flow on to an enclosing loop or function.
+    Propagate(ControlFlow),
+}
+
+impl LoopStep {
+    fn after(flow: ControlFlow, label: &Option<Rc<str>>) -> Self {
+        // An unlabeled jump targets the innermost loop
+        let targets = |target: &Option<Rc<str>>| target.is_none() || target == label;
+        match flow {
+            ControlFlow::Normal => LoopStep::Next,
+            ControlFlow::Continue(target) if targets(&target) => LoopStep::Next,
+            ControlFlow::Break(target) if targets(&target) => LoopStep::Exit,
+            flow => LoopStep::Propagate(flow),
+        }
+    }
+}
+
+fn execute_block(
+    statements: &[ASTNode],
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ControlFlow, RuntimeError> {
+    if let Some(depth) = &mut interp.trace_depth {
+        *depth += 1;
+    }
+    let flow = run_statements(statements, env, interp);
+    if let Some(depth) = &mut interp.trace_depth {
+        *depth -= 1;
+    }
+    flow
+}
+
+/// Runs statements in order until one returns or fails. `defer` blocks are
+/// collected as they are reached and run afterwards, last first, however
+/// the statements finished; they see variables as they are at that point.
+fn run_statements(
+    statements: &[ASTNode],
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ControlFlow, RuntimeError> {
+    let mut deferred = Vec::new();
+    let mut flow = Ok(ControlFlow::Normal);
+    for stmt in statements {
+        if let ASTNode::DeferStatement(body) = stmt {
+            deferred.push(body);
+            // A tail call would run after leaving this scope, and so after
+            // its deferred blocks
+            interp.pending_defers += 1;
+            continue;
+        }
+        flow = execute(stmt, env, interp);
+        if !matches!(flow, Ok(ControlFlow::Normal)) {
+            break;
+        }
+    }
+    interp.pending_defers -= deferred.len();
+    for body in deferred.into_iter().rev() {
+        let defer_env = Environment::new(Some(env.clone()));
+        // An earlier error takes precedence over one from a deferred block
+        if let Err(e) = execute_block(body, &defer_env, interp) {
+            if flow.is_ok() {
+                flow = Err(e);
+            }
+        }
+    }
+    flow
+}
+
+fn execute(
+    node: &ASTNode,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ControlFlow, RuntimeError> {
+    if let Some(depth) = interp.trace_depth {
+        // Empty statements have nothing worth showing
+        if !matches!(node, ASTNode::Program(_)) {
+            eprintln!("[trace] {}{}", "  ".repeat(depth), formatter::statement_summary(node));
+        }
+    }
     match node {
         ASTNode::PrintStatement(expr) => {
-            let value = evaluate(*expr, env, functions);
+            let value = evaluate(expr, env, interp)?;
             match value {
-                ASTNode::StringLiteral(s) => println!("{}", s),
-                ASTNode::NumberLiteral(n) => println!("{}", n),
+                ASTNode::NumberLiteral(n) => {
+                    println!("{}", format_number(n, interp.thousands_separators))
+                }
+                ASTNode::StringLiteral(_)
+                | ASTNode::BooleanLiteral(_)
+                | ASTNode::NilLiteral
+                | ASTNode::FunctionDeclaration { .. }
+                | ASTNode::Memoized { .. }
+                | ASTNode::Array(_)
+                | ASTNode::Map(_) => println!("{}", format_value(&value)),
                 _ => println!("Cannot print value"),
             }
         }
         ASTNode::VariableAssignment(name, expr) => {
-            let value = evaluate(*expr, env, functions);
-            if !env.assign(&name, value.clone()) {
-                env.define(name, value);
+            let value = evaluate(expr, env, interp)?;
+            if !env.assign(name, value.clone()) {
+                env.define(name.clone(), value);
             }
         }
+        ASTNode::GlobalDeclaration(name) => {
+            env.declare_global(name.clone());
+        }
+        ASTNode::ImportStatement { path, alias } => import_file(path, alias.as_ref(), interp)?,
+        ASTNode::LetDeclaration(name, expr) => {
+            // Always binds in the current scope, shadowing any outer variable
+            let value = evaluate(expr, env, interp)?;
+            env.define(name.clone(), value);
+        }
         ASTNode::IfStatement {
             condition,
             then_branch,
             else_branch,
         } => {
-            let cond_value = evaluate(*condition, env, functions);
+            // The condition is evaluated exactly once, in the enclosing
+            // scope; only the chosen branch runs, in its own child scope.
+            let cond_value = evaluate(condition, env, interp)?;
             if is_truthy(&cond_value) {
-                let mut then_env = Environment::new(Some(Box::new(env.clone())));
-                for stmt in then_branch {
-                    execute(stmt, &mut then_env, functions);
-                }
+                let then_env = Environment::new(Some(env.clone()));
+                return execute_block(then_branch, &then_env, interp);
             } else if let Some(else_branch) = else_branch {
-                let mut else_env = Environment::new(Some(Box::new(env.clone())));
-                for stmt in else_branch {
-                    execute(stmt, &mut else_env, functions);
+                let else_env = Environment::new(Some(env.clone()));
+                return execute_block(else_branch, &else_env, interp);
+            }
+        }
+        ASTNode::GuardStatement { condition, else_block } => {
+            let cond_value = evaluate(condition, env, interp)?;
+            if !is_truthy(&cond_value) {
+                let else_env = Environment::new(Some(env.clone()));
+                return execute_block(else_block, &else_env, interp);
+            }
+        }
+        ASTNode::WhileStatement { label, condition, body } => {
+            let mut iterations = 0;
+            loop {
+                let cond_value = evaluate(condition, env, interp)?;
+                if !is_truthy(&cond_value) {
+                    break;
+                }
+                iterations += 1;
+                check_iterations(iterations, interp)?;
+                let body_env = Environment::new(Some(env.clone()));
+                match LoopStep::after(execute_block(body, &body_env, interp)?, label) {
+                    LoopStep::Next => {}
+                    LoopStep::Exit => break,
+                    LoopStep::Propagate(flow) => return Ok(flow),
                 }
             }
         }
-        ASTNode::FunctionDeclaration { name, parameters, body } => {
-            functions.insert(name.clone(), ASTNode::FunctionDeclaration { name, parameters, body });
+        ASTNode::RepeatStatement { label, count, body } => {
+            let count = match evaluate(count, env, interp)? {
+                ASTNode::NumberLiteral(n) if n >= 0.0 && n.fract() == 0.0 => n as u64,
+                other => {
+                    return Err(format!(
+                        "repeat count must be a non-negative integer, got {}",
+                        format_element(&other)
+                    )
+                    .into())
+                }
+            };
+            for iterations in 1..=count {
+                check_iterations(iterations, interp)?;
+                let body_env = Environment::new(Some(env.clone()));
+                match LoopStep::after(execute_block(body, &body_env, interp)?, label) {
+                    LoopStep::Next => {}
+                    LoopStep::Exit => break,
+                    LoopStep::Propagate(flow) => return Ok(flow),
+                }
+            }
         }
-        ASTNode::FunctionCall { name, arguments } => {
-            let function = functions.get(&name).cloned();
-            if let Some(ASTNode::FunctionDeclaration { parameters, body, .. }) = function {
-                if arguments.len() != parameters.len() {
-                    eprintln!("Error: Incorrect number of arguments for function '{}'", name);
-                    return;
+        ASTNode::ForeachStatement { label, index, variable, iterable, body } => {
+            for (iterations, item) in (1..).zip(iterate(iterable, env, interp)?) {
+                check_iterations(iterations, interp)?;
+                // Each iteration gets a fresh scope holding the loop variables
+                let loop_env = Environment::new(Some(env.clone()));
+                if let Some(index) = index {
+                    loop_env.define(index.clone(), ASTNode::NumberLiteral((iterations - 1) as f64));
                 }
-                let mut local_env = Environment::new(Some(Box::new(env.clone())));
-                for (param, arg) in parameters.iter().zip(arguments) {
-                    let arg_value = evaluate(arg, env, functions);
-                    local_env.define(param.clone(), arg_value);
+                loop_env.define(variable.clone(), item);
+                match LoopStep::after(execute_block(body, &loop_env, interp)?, label) {
+                    LoopStep::Next => {}
+                    LoopStep::Exit => break,
+                    LoopStep::Propagate(flow) => return Ok(flow),
                 }
-                for stmt in body {
-                    execute(stmt, &mut local_env, functions);
+            }
+        }
+        ASTNode::FunctionDeclaration { name, .. } => {
+            interp.functions.insert(name.clone(), node.clone());
+        }
+        ASTNode::IndexAssignment { target, index, value } => {
+            let target_value = evaluate(target, env, interp)?;
+            let index_value = evaluate(index, env, interp)?;
+            let value = evaluate(value, env, interp)?;
+            store_index(&target_value, &index_value, value)?;
+        }
+        ASTNode::ChainedAssignment { targets, value } => {
+            let value = evaluate(value, env, interp)?;
+            for target in targets.iter().rev() {
+                assign_to(target, value.clone(), env, interp)?;
+            }
+        }
+        ASTNode::ParallelAssignment { targets, values } => {
+            // Every value is evaluated before any target changes, so
+            // `a, b = b, a` swaps.
+            let values: Vec<ASTNode> = values
+                .iter()
+                .map(|value| evaluate(value, env, interp))
+                .collect::<Result<_, _>>()?;
+            for (target, value) in targets.iter().zip(values) {
+                assign_to(target, value, env, interp)?;
+            }
+        }
+        ASTNode::Program(statements) => {
+            return execute_block(statements, env, interp);
+        }
+        ASTNode::TryCatch {
+            try_block,
+            error_name,
+            catch_block,
+        } => {
+            let try_env = Environment::new(Some(env.clone()));
+            // A tail call would run after leaving the try, escaping its catch
+            interp.try_depth += 1;
+            let result = execute_block(try_block, &try_env, interp);
+            interp.try_depth -= 1;
+            match result {
+                Ok(flow) => return Ok(flow),
+                Err(error) => {
+                    let error_value = match error {
+                        RuntimeError::Message(message) => ASTNode::StringLiteral(message),
+                        RuntimeError::Thrown(value) => value,
+                    };
+                    let catch_env = Environment::new(Some(env.clone()));
+                    catch_env.define(error_name.clone(), error_value);
+                    return execute_block(catch_block, &catch_env, interp);
                 }
-            } else {
-                eprintln!("Error: Undefined function '{}'", name);
             }
         }
-        _ => {
-            // Handle other nodes if necessary
+        ASTNode::ThrowStatement(expr) => {
+            let value = evaluate(expr, env, interp)?;
+            return Err(RuntimeError::Thrown(value));
+        }
+        ASTNode::StopStatement => return Ok(ControlFlow::Stop),
+        ASTNode::BreakStatement(label) => return Ok(ControlFlow::Break(label.clone())),
+        ASTNode::ContinueStatement(label) => return Ok(ControlFlow::Continue(label.clone())),
+        ASTNode::ReturnStatement(Some(expr)) if is_self_tail_call(expr, env, interp) => {
+            let ASTNode::FunctionCall { arguments, .. } = &**expr else {
+                unreachable!()
+            };
+            let args = arguments
+                .iter()
+                .map(|arg| evaluate(arg, env, interp))
+                .collect::<Result<_, _>>()?;
+            return Ok(ControlFlow::TailCall(args));
+        }
+        ASTNode::ReturnStatement(expr) => {
+            let value = match expr {
+                Some(expr) => evaluate(expr, env, interp)?,
+                None => ASTNode::NumberLiteral(0.0),
+            };
+            return Ok(ControlFlow::Return(value));
+        }
+        // `run_statements` collects these before they would get here
+        ASTNode::DeferStatement(_) => {}
+        expr => {
+            // An expression statement runs for its effects; its value is dropped
+            evaluate(expr, env, interp)?;
         }
     }
+    Ok(ControlFlow::Normal)
 }
 
-fn evaluate(node: ASTNode, env: &mut Environment, functions: &mut HashMap<String, ASTNode>) -> ASTNode {
+fn evaluate(
+    node: &ASTNode,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ASTNode, RuntimeError> {
     match node {
-        ASTNode::NumberLiteral(n) => ASTNode::NumberLiteral(n),
-        ASTNode::StringLiteral(s) => ASTNode::StringLiteral(s),
+        ASTNode::NumberLiteral(_)
+        | ASTNode::StringLiteral(_)
+        | ASTNode::BooleanLiteral(_)
+        | ASTNode::NilLiteral => Ok(node.clone()),
+        ASTNode::PrefixedString { tag, value } => match &**tag {
+            "json" => Ok(json::parse_json(value)?),
+            _ => Err(format!("Unknown string prefix '{}'", tag).into()),
+        },
         ASTNode::Identifier(name) => {
-            if let Some(value) = env.get(&name) {
-                value
+            if let Some(value) = env.get(name) {
+                Ok(value)
+            } else if let Some(function) = interp.functions.get(name) {
+                // Naming a declared function yields it as a value
+                Ok(function.clone())
+            } else if interp.strict {
+                Err(format!("Undefined variable '{}'", name).into())
             } else {
                 eprintln!("Error: Undefined variable '{}'", name);
-                ASTNode::NumberLiteral(0.0) // Or handle appropriately
+                Ok(ASTNode::NumberLiteral(0.0)) // Or handle appropriately
             }
         }
         ASTNode::BinaryExpression { left, operator, right } => {
-            let left_value = evaluate(*left, env, functions);
-            let right_value = evaluate(*right, env, functions);
-            match (left_value, right_value) {
-                (ASTNode::NumberLiteral(l), ASTNode::NumberLiteral(r)) => match operator {
-                    Token::Plus => ASTNode::NumberLiteral(l + r),
-                    Token::Minus => ASTNode::NumberLiteral(l - r),
-                    Token::Star => ASTNode::NumberLiteral(l * r),
-                    Token::Slash => ASTNode::NumberLiteral(l / r),
-                    Token::EqualEqual => ASTNode::NumberLiteral((l == r) as i32 as f64),
-                    Token::NotEqual => ASTNode::NumberLiteral((l != r) as i32 as f64),
-                    Token::LessThan => ASTNode::NumberLiteral((l < r) as i32 as f64),
-                    Token::GreaterThan => ASTNode::NumberLiteral((l > r) as i32 as f64),
-                    Token::LessEqual => ASTNode::NumberLiteral((l <= r) as i32 as f64),
-                    Token::GreaterEqual => ASTNode::NumberLiteral((l >= r) as i32 as f64),
-                    _ => {
-                        eprintln!("Error: Unsupported operator");
-                        ASTNode::NumberLiteral(0.0)
-                    }
-                },
-                (ASTNode::StringLiteral(l), ASTNode::StringLiteral(r)) => match operator {
-                    Token::Plus => ASTNode::StringLiteral(l + &r),
-                    Token::EqualEqual => ASTNode::NumberLiteral((l == r) as i32 as f64),
-                    Token::NotEqual => ASTNode::NumberLiteral((l != r) as i32 as f64),
-                    _ => {
-                        eprintln!("Error: Unsupported operator for strings");
-                        ASTNode::NumberLiteral(0.0)
-                    }
-                },
-                (ASTNode::StringLiteral(l), ASTNode::NumberLiteral(r)) => match operator {
-                    Token::Plus => ASTNode::StringLiteral(l + &r.to_string()),
-                    _ => {
-                        eprintln!("Error: Unsupported operator for string and number");
-                        ASTNode::NumberLiteral(0.0)
-                    }
-                },
-                (ASTNode::NumberLiteral(l), ASTNode::StringLiteral(r)) => match operator {
-                    Token::Plus => ASTNode::StringLiteral(l.to_string() + &r),
-                    _ => {
-                        eprintln!("Error: Unsupported operator for number and string");
-                        ASTNode::NumberLiteral(0.0)
-                    }
-                },
-                _ => {
-                    eprintln!("Error: Invalid operands");
-                    ASTNode::NumberLiteral(0.0)
-                }
+            let left_value = evaluate(left, env, interp)?;
+            if *operator == Token::QuestionQuestion {
+                // The fallback is only evaluated when it is needed
+                return match left_value {
+                    ASTNode::NilLiteral => evaluate(right, env, interp),
+                    value => Ok(value),
+                };
+            }
+            let right_value = evaluate(right, env, interp)?;
+            let result = binary_operation(operator, left_value, right_value)?;
+            match result {
+                ASTNode::NumberLiteral(n) if interp.numeric_guard && !n.is_finite() => Err(format!(
+                    "Arithmetic '{}' produced a non-finite result ({})",
+                    formatter::operator_symbol(operator),
+                    n
+                )
+                .into()),
+                _ => Ok(result),
             }
         }
         ASTNode::FunctionCall { name, arguments } => {
-            let function = functions.get(&name).cloned();
-            if let Some(ASTNode::FunctionDeclaration { parameters, body, .. }) = function {
-                if arguments.len() != parameters.len() {
-                    eprintln!("Error: Incorrect number of arguments for function '{}'", name);
-                    return ASTNode::NumberLiteral(0.0);
+            let args = arguments
+                .iter()
+                .map(|arg| evaluate(arg, env, interp))
+                .collect::<Result<_, _>>()?;
+            // A variable holding a function value takes precedence over a
+            // declared function of the same name, and both over a built-in.
+            match user_function(name, env, interp) {
+                Some(function) => call_function(function, args, interp),
+                None if is_builtin(name) => call_builtin(name, args, env, interp),
+                None => Err(format!("Undefined function '{}'", name).into()),
+            }
+        }
+        ASTNode::SequenceExpression(expressions) => {
+            let mut value = ASTNode::NilLiteral;
+            for expr in expressions {
+                value = evaluate(expr, env, interp)?;
+            }
+            Ok(value)
+        }
+        ASTNode::CallExpression { callee, arguments } => {
+            let function = evaluate(callee, env, interp)?;
+            let args = arguments
+                .iter()
+                .map(|arg| evaluate(arg, env, interp))
+                .collect::<Result<_, _>>()?;
+            call_function(function, args, interp)
+        }
+        ASTNode::UnaryExpression { operator, operand } => {
+            let operand_value = evaluate(operand, env, interp)?;
+            match (operator, operand_value) {
+                (Token::Bang, value) => Ok(ASTNode::BooleanLiteral(!is_truthy(&value))),
+                (Token::Minus, ASTNode::NumberLiteral(n)) => Ok(ASTNode::NumberLiteral(-n)),
+                (Token::Minus, _) => Err("Invalid operand for unary operator".into()),
+                _ => Err("Unsupported unary operator".into()),
+            }
+        }
+        ASTNode::ArrayLiteral(elements) => {
+            let values = elements
+                .iter()
+                .map(|element| evaluate(element, env, interp))
+                .collect::<Result<_, _>>()?;
+            Ok(ASTNode::Array(Rc::new(RefCell::new(values))))
+        }
+        ASTNode::MapLiteral(entries) => {
+            let mut values = HashMap::new();
+            for (key, value) in entries {
+                let key = map_key(&evaluate(key, env, interp)?)?;
+                let value = evaluate(value, env, interp)?;
+                values.insert(key, value);
+            }
+            Ok(ASTNode::Map(Rc::new(RefCell::new(values))))
+        }
+        ASTNode::IndexExpression { target, index } => {
+            let target_value = evaluate(target, env, interp)?;
+            let index_value = evaluate(index, env, interp)?;
+            Ok(index_into(&target_value, &index_value)?)
+        }
+        ASTNode::OptionalIndexExpression { target, index } => {
+            let target_value = evaluate(target, env, interp)?;
+            if let ASTNode::NilLiteral = target_value {
+                return Ok(ASTNode::NilLiteral);
+            }
+            let index_value = evaluate(index, env, interp)?;
+            Ok(index_into(&target_value, &index_value)?)
+        }
+        ASTNode::SliceExpression { target, start, end } => {
+            let target_value = evaluate(target, env, interp)?;
+            let start = match start {
+                Some(start) => Some(evaluate(start, env, interp)?),
+                None => None,
+            };
+            let end = match end {
+                Some(end) => Some(evaluate(end, env, interp)?),
+                None => None,
+            };
+            match target_value {
+                ASTNode::Array(elements) => {
+                    let elements = elements.borrow();
+                    let (start, end) = slice_bounds(start.as_ref(), end.as_ref(), elements.len())?;
+                    let sliced = elements[start..end].to_vec();
+                    Ok(ASTNode::Array(Rc::new(RefCell::new(sliced))))
                 }
-                let mut local_env = Environment::new(Some(Box::new(env.clone())));
-                for (param, arg) in parameters.iter().zip(arguments) {
-                    let arg_value = evaluate(arg, env, functions);
-                    local_env.define(param.clone(), arg_value);
+                ASTNode::StringLiteral(s) => {
+                    let chars: Vec<char> = s.chars().collect();
+                    let (start, end) = slice_bounds(start.as_ref(), end.as_ref(), chars.len())?;
+                    Ok(ASTNode::StringLiteral(chars[start..end].iter().collect()))
                 }
-                for stmt in body {
-                    execute(stmt, &mut local_env, functions);
+                _ => Err("Cannot slice a value that is not an array or string".into()),
+            }
+        }
+        ASTNode::MethodCall { receiver, method, arguments } => {
+            if let ASTNode::Identifier(alias) = &**receiver {
+                if let Some(namespace) = interp.namespaces.get(alias).cloned() {
+                    let args = arguments
+                        .iter()
+                        .map(|arg| evaluate(arg, env, interp))
+                        .collect::<Result<_, _>>()?;
+                    return call_in_namespace(&namespace, alias, method, args, interp);
                 }
-                // For simplicity, functions do not return values in this example
-                ASTNode::NumberLiteral(0.0)
+            }
+            if !is_builtin(method) {
+                return Err(format!("Unknown method '{}'", method).into());
+            }
+            let mut args = vec![evaluate(receiver, env, interp)?];
+            for arg in arguments {
+                args.push(evaluate(arg, env, interp)?);
+            }
+            call_builtin(method, args, env, interp)
+        }
+        ASTNode::Array(_) | ASTNode::Map(_) => Ok(node.clone()),
+        _ => Err("Unsupported AST node in evaluation".into()),
+    }
+}
+
+/// The user-defined function a call to `name` refers to, if any: a variable
+/// holding a function value, or else a declared function.
+fn user_function(name: &str, env: &Environment, interp: &Interpreter) -> Option<ASTNode> {
+    match env.get(name) {
+        Some(value @ (ASTNode::FunctionDeclaration { .. } | ASTNode::Memoized { .. })) => {
+            Some(value)
+        }
+        _ => interp.functions.get(name).cloned(),
+    }
+}
+
+/// Stops a runaway loop once it passes the `--max-iters` limit.
+fn check_iterations(iterations: u64, interp: &Interpreter) -> Result<(), RuntimeError> {
+    match interp.max_iterations {
+        Some(limit) if iterations > limit => {
+            Err(format!("Loop exceeded the maximum of {} iterations", limit).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The values a `foreach` visits. Ranges produce their numbers one at a
+/// time rather than from a materialized array.
+enum Iteration {
+    Values(std::vec::IntoIter<ASTNode>),
+    Range { next: f64, end: f64, step: f64 },
+}
+
+impl Iterator for Iteration {
+    type Item = ASTNode;
+
+    fn next(&mut self) -> Option<ASTNode> {
+        match self {
+            Iteration::Values(values) => values.next(),
+            Iteration::Range { next, end, step } => {
+                if (*step > 0.0 && *next < *end) || (*step < 0.0 && *next > *end) {
+                    let value = *next;
+                    *next += *step;
+                    Some(ASTNode::NumberLiteral(value))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Evaluates the iterable of a `foreach`. A `range(...)` call written
+/// directly in the loop header is iterated lazily; arrays are snapshotted,
+/// strings yield their characters and maps their keys in sorted order.
+fn iterate(
+    iterable: &ASTNode,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<Iteration, RuntimeError> {
+    if let ASTNode::FunctionCall { name, arguments } = iterable {
+        if &**name == "range" && user_function(name, env, interp).is_none() {
+            let args: Vec<ASTNode> = arguments
+                .iter()
+                .map(|arg| evaluate(arg, env, interp))
+                .collect::<Result<_, _>>()?;
+            let (start, end, step) = range_bounds(&args)?;
+            return Ok(Iteration::Range { next: start, end, step });
+        }
+    }
+    match evaluate(iterable, env, interp)? {
+        ASTNode::Array(elements) => Ok(Iteration::Values(elements.borrow().clone().into_iter())),
+        ASTNode::StringLiteral(s) => {
+            let chars: Vec<ASTNode> =
+                s.chars().map(|c| ASTNode::StringLiteral(c.to_string())).collect();
+            Ok(Iteration::Values(chars.into_iter()))
+        }
+        ASTNode::Map(entries) => {
+            let mut keys: Vec<String> = entries.borrow().keys().cloned().collect();
+            keys.sort();
+            let keys: Vec<ASTNode> = keys.into_iter().map(ASTNode::StringLiteral).collect();
+            Ok(Iteration::Values(keys.into_iter()))
+        }
+        other => Err(format!("Cannot iterate over a value of type {}", type_name(&other)).into()),
+    }
+}
+
+/// Runs the top-level statements of another file. The path is resolved
+/// relative to the directory of the importing script. Without an alias the
+/// file shares the global scope; with one it gets its own namespace.
+fn import_file(
+    path: &str,
+    alias: Option<&Rc<str>>,
+    interp: &mut Interpreter,
+) -> Result<(), RuntimeError> {
+    let base = interp.script_path.as_deref().and_then(Path::parent);
+    let resolved = match base {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    };
+    let resolved = fs::canonicalize(&resolved)
+        .map_err(|e| format!("Cannot import '{}': {}", path, e))?;
+    if interp.importing.contains(&resolved) {
+        return Err(format!("Cyclic import of '{}'", path).into());
+    }
+    let code = fs::read_to_string(&resolved)
+        .map_err(|e| format!("Cannot import '{}': {}", path, e))?;
+    let ast_nodes = lex(&code)
+        .and_then(|(tokens, positions)| parse(&tokens, &positions).map_err(|e| e.to_string()))
+        .map_err(|e| format!("In '{}': {}", path, e))?;
+
+    let Some(alias) = alias else {
+        return run_imported(resolved, ast_nodes, interp);
+    };
+    let functions = std::mem::take(&mut interp.functions);
+    let globals = std::mem::replace(&mut interp.globals, global_scope());
+    let result = run_imported(resolved, ast_nodes, interp);
+    let namespace = Namespace {
+        functions: std::mem::replace(&mut interp.functions, functions),
+        globals: std::mem::replace(&mut interp.globals, globals),
+    };
+    result?;
+    interp.namespaces.insert(alias.clone(), namespace);
+    Ok(())
+}
+
+/// Runs juul source in the calling scope for `eval`. The result is the value
+/// of the final statement if it is an expression, and nil otherwise.
+fn eval_source(
+    source: &str,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ASTNode, RuntimeError> {
+    let ast_nodes = lex(source)
+        .and_then(|(tokens, positions)| {
+            parse_eval(&tokens, &positions).map_err(|e| e.to_string())
+        })
+        .map_err(|e| format!("In eval(): {}", e))?;
+    let mut ast_nodes = optimizer::fold_constants(ast_nodes);
+    let last = ast_nodes.pop_if(|node| !is_statement(node));
+    if let ControlFlow::Stop = run_statements(&ast_nodes, env, interp)? {
+        return Ok(ASTNode::NilLiteral);
+    }
+    match last {
+        Some(expr) => evaluate(&expr, env, interp),
+        None => Ok(ASTNode::NilLiteral),
+    }
+}
+
+fn is_statement(node: &ASTNode) -> bool {
+    matches!(
+        node,
+        ASTNode::Program(_)
+            | ASTNode::PrintStatement(_)
+            | ASTNode::VariableAssignment(..)
+            | ASTNode::LetDeclaration(..)
+            | ASTNode::GlobalDeclaration(_)
+            | ASTNode::ImportStatement { .. }
+            | ASTNode::IfStatement { .. }
+            | ASTNode::GuardStatement { .. }
+            | ASTNode::DeferStatement(_)
+            | ASTNode::WhileStatement { .. }
+            | ASTNode::RepeatStatement { .. }
+            | ASTNode::BreakStatement(_)
+            | ASTNode::ContinueStatement(_)
+            | ASTNode::StopStatement
+            | ASTNode::ForeachStatement { .. }
+            | ASTNode::FunctionDeclaration { .. }
+            | ASTNode::ReturnStatement(_)
+            | ASTNode::ThrowStatement(_)
+            | ASTNode::TryCatch { .. }
+            | ASTNode::ChainedAssignment { .. }
+            | ASTNode::ParallelAssignment { .. }
+            | ASTNode::IndexAssignment { .. }
+    )
+}
+
+fn run_imported(
+    path: PathBuf,
+    ast_nodes: Vec<ASTNode>,
+    interp: &mut Interpreter,
+) -> Result<(), RuntimeError> {
+    let previous_path = interp.script_path.replace(path.clone());
+    interp.importing.push(path);
+    let globals = interp.globals.clone();
+    let result = run_statements(&optimizer::fold_constants(ast_nodes), &globals, interp);
+    interp.importing.pop();
+    interp.script_path = previous_path;
+    result.map(|_| ())
+}
+
+/// Calls `alias.name(args)` with the namespace's functions and top-level
+/// scope swapped in for the duration of the call.
+fn call_in_namespace(
+    namespace: &Namespace,
+    alias: &str,
+    name: &str,
+    args: Vec<ASTNode>,
+    interp: &mut Interpreter,
+) -> Result<ASTNode, RuntimeError> {
+    let Some(function) = namespace.functions.get(name).cloned() else {
+        return Err(format!("Undefined function '{}.{}'", alias, name).into());
+    };
+    let functions = std::mem::replace(&mut interp.functions, namespace.functions.clone());
+    let globals = std::mem::replace(&mut interp.globals, namespace.globals.clone());
+    let result = call_function(function, args, interp);
+    interp.functions = functions;
+    interp.globals = globals;
+    result
+}
+
+/// Stores `value` into a variable or an indexed element, as named by the
+/// left-hand side of an assignment.
+fn assign_to(
+    target: &ASTNode,
+    value: ASTNode,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<(), RuntimeError> {
+    match target {
+        ASTNode::Identifier(name) => {
+            if !env.assign(name, value.clone()) {
+                env.define(name.clone(), value);
+            }
+        }
+        ASTNode::IndexExpression { target, index } => {
+            let target_value = evaluate(target, env, interp)?;
+            let index_value = evaluate(index, env, interp)?;
+            store_index(&target_value, &index_value, value)?;
+        }
+        _ => return Err("Invalid assignment target".into()),
+    }
+    Ok(())
+}
+
+/// Writes one element of an array or map. The target of a nested
+/// assignment such as `a[i][j] = v` is read as usual; since collections are
+/// shared, writing into what it yields updates the outer structure too.
+fn store_index(target: &ASTNode, index: &ASTNode, value: ASTNode) -> Result<(), String> {
+    match target {
+        ASTNode::Array(elements) => {
+            let mut elements = elements.borrow_mut();
+            let i = sequence_index(index, elements.len(), "Array")?;
+            elements[i] = value;
+        }
+        ASTNode::Map(entries) => {
+            let key = map_key(index)?;
+            entries.borrow_mut().insert(key, value);
+        }
+        // Through a chain like `a["k"][0] = v`, this is usually a missing key
+        _ => {
+            return Err(format!(
+                "Cannot assign into an element of a value of type {}",
+                type_name(target)
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Reads one element of an array or map.
+fn index_into(target: &ASTNode, index: &ASTNode) -> Result<ASTNode, String> {
+    match target {
+        ASTNode::Array(elements) => {
+            let elements = elements.borrow();
+            let i = sequence_index(index, elements.len(), "Array")?;
+            Ok(elements[i].clone())
+        }
+        ASTNode::Map(entries) => {
+            let key = map_key(index)?;
+            // A missing key reads as nil so lookups can fall back with `??`
+            Ok(entries.borrow().get(&key).cloned().unwrap_or(ASTNode::NilLiteral))
+        }
+        ASTNode::StringLiteral(s) => {
+            // Indexed by character, like `len` and slicing
+            let chars: Vec<char> = s.chars().collect();
+            let i = sequence_index(index, chars.len(), "String")?;
+            Ok(ASTNode::StringLiteral(chars[i].to_string()))
+        }
+        _ => Err("Cannot index into a non-collection value".into()),
+    }
+}
+
+/// Applies a binary operator to two already-evaluated operands.
+///
+/// Numbers follow IEEE 754 with a few pinned-down edge cases: `-0 == 0` is
+/// true, dividing by zero of either sign is an error rather than an
+/// infinity (the message tells a negative zero apart, since it would have
+/// flipped the infinity's sign), and NaN (only reachable with the numeric
+/// guard off) compares unequal to everything, itself included, and false
+/// under `<`/`>`.
+fn binary_operation(operator: &Token, left: ASTNode, right: ASTNode) -> Result<ASTNode, String> {
+    match operator {
+        Token::EqualEqual => return Ok(ASTNode::BooleanLiteral(values_equal(&left, &right))),
+        Token::NotEqual => return Ok(ASTNode::BooleanLiteral(!values_equal(&left, &right))),
+        Token::CaretCaret => {
+            return Ok(ASTNode::BooleanLiteral(is_truthy(&left) != is_truthy(&right)))
+        }
+        Token::In => return contains(&right, &left).map(ASTNode::BooleanLiteral),
+        _ => {}
+    }
+    match (left, right) {
+        (ASTNode::NumberLiteral(l), ASTNode::NumberLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::NumberLiteral(l + r)),
+            Token::Minus => Ok(ASTNode::NumberLiteral(l - r)),
+            Token::Star => Ok(ASTNode::NumberLiteral(l * r)),
+            Token::Slash if r == 0.0 && r.is_sign_negative() => {
+                Err("Division by zero (negative zero)".into())
+            }
+            Token::Slash if r == 0.0 => Err("Division by zero".into()),
+            Token::Slash => Ok(ASTNode::NumberLiteral(l / r)),
+            Token::LessThan => Ok(ASTNode::BooleanLiteral(l < r)),
+            Token::GreaterThan => Ok(ASTNode::BooleanLiteral(l > r)),
+            Token::LessEqual => Ok(ASTNode::BooleanLiteral(l <= r)),
+            Token::GreaterEqual => Ok(ASTNode::BooleanLiteral(l >= r)),
+            _ => Err("Unsupported operator".into()),
+        },
+        (ASTNode::StringLiteral(l), ASTNode::StringLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::StringLiteral(l + &r)),
+            _ => Err("Unsupported operator for strings".into()),
+        },
+        (ASTNode::StringLiteral(l), ASTNode::BooleanLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::StringLiteral(l + &r.to_string())),
+            _ => Err("Unsupported operator for string and boolean".into()),
+        },
+        (ASTNode::BooleanLiteral(l), ASTNode::StringLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::StringLiteral(l.to_string() + &r)),
+            _ => Err("Unsupported operator for boolean and string".into()),
+        },
+        (ASTNode::StringLiteral(l), ASTNode::NumberLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::StringLiteral(l + &r.to_string())),
+            _ => Err("Unsupported operator for string and number".into()),
+        },
+        (ASTNode::NumberLiteral(l), ASTNode::StringLiteral(r)) => match operator {
+            Token::Plus => Ok(ASTNode::StringLiteral(l.to_string() + &r)),
+            _ => Err("Unsupported operator for number and string".into()),
+        },
+        (ASTNode::Array(l), ASTNode::Array(r)) => match operator {
+            Token::Plus => {
+                let mut elements = l.borrow().clone();
+                elements.extend(r.borrow().iter().cloned());
+                Ok(ASTNode::Array(Rc::new(RefCell::new(elements))))
+            }
+            _ => Err("Unsupported operator for arrays".into()),
+        },
+        (l @ ASTNode::Array(_), r) | (l, r @ ASTNode::Array(_)) if *operator == Token::Plus => {
+            Err(format!("Cannot add {} and {}", type_name(&l), type_name(&r)))
+        }
+        _ => Err("Invalid operands".into()),
+    }
+}
+
+/// Implements `item in container`: element equality for arrays, key
+/// presence for maps.
+fn contains(container: &ASTNode, item: &ASTNode) -> Result<bool, String> {
+    match container {
+        ASTNode::Array(elements) => Ok(elements.borrow().iter().any(|e| values_equal(e, item))),
+        ASTNode::Map(entries) => Ok(entries.borrow().contains_key(&map_key(item)?)),
+        _ => Err(format!("Cannot use 'in' with a value of type {}", type_name(container))),
+    }
+}
+
+/// Structural equality: collections compare element by element, functions
+/// by identity, and values of different types are never equal.
+fn values_equal(a: &ASTNode, b: &ASTNode) -> bool {
+    equal_within(a, b, &mut Vec::new())
+}
+
+/// Compares two values, given the pairs of collections whose comparison is
+/// already under way. Meeting such a pair again means both sides repeat the
+/// same way, so it adds no difference.
+fn equal_within(a: &ASTNode, b: &ASTNode, open: &mut Vec<(*const (), *const ())>) -> bool {
+    let pair = collection_ptr(a).zip(collection_ptr(b));
+    if let Some(pair) = pair {
+        if open.contains(&pair) {
+            return true;
+        }
+        open.push(pair);
+    }
+    let equal = match (a, b) {
+        (ASTNode::NumberLiteral(l), ASTNode::NumberLiteral(r)) => l == r,
+        (ASTNode::StringLiteral(l), ASTNode::StringLiteral(r)) => l == r,
+        (ASTNode::BooleanLiteral(l), ASTNode::BooleanLiteral(r)) => l == r,
+        (ASTNode::NilLiteral, ASTNode::NilLiteral) => true,
+        (ASTNode::Array(l), ASTNode::Array(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| equal_within(l, r, open))
+        }
+        (ASTNode::Map(l), ASTNode::Map(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len()
+                && l.iter().all(|(key, l)| r.get(key).is_some_and(|r| equal_within(l, r, open)))
+        }
+        // The same declaration shares its body, so a redeclared function
+        // differs from the one it replaced
+        (
+            ASTNode::FunctionDeclaration { body: l, .. },
+            ASTNode::FunctionDeclaration { body: r, .. },
+        ) => Rc::ptr_eq(l, r),
+        (ASTNode::Memoized { cache: l, .. }, ASTNode::Memoized { cache: r, .. }) => Rc::ptr_eq(l, r),
+        _ => false,
+    };
+    if pair.is_some() {
+        open.pop();
+    }
+    equal
+}
+
+/// Identifies a shared array or map, so that walking a value can notice
+/// when it reaches a collection that contains itself.
+fn collection_ptr(value: &ASTNode) -> Option<*const ()> {
+    match value {
+        ASTNode::Array(elements) => Some(Rc::as_ptr(elements).cast()),
+        ASTNode::Map(entries) => Some(Rc::as_ptr(entries).cast()),
+        _ => None,
+    }
+}
+
+/// Finds the first place where two values differ, returning its path (such
+/// as `[1].name`, empty for the values themselves) and a description of the
+/// mismatch, or `None` if the values are equal. `open` holds the pairs of
+/// collections already being compared, as in `equal_within`.
+fn first_difference(
+    left: &ASTNode,
+    right: &ASTNode,
+    open: &mut Vec<(*const (), *const ())>,
+) -> Option<(String, String)> {
+    let pair = collection_ptr(left).zip(collection_ptr(right));
+    if let Some(pair) = pair {
+        if open.contains(&pair) {
+            return None;
+        }
+        open.push(pair);
+    }
+    let difference = match (left, right) {
+        (ASTNode::Array(l), ASTNode::Array(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            let difference = l.iter().zip(r.iter()).enumerate().find_map(|(i, (l, r))| {
+                let (path, difference) = first_difference(l, r, open)?;
+                Some((format!("[{}]{}", i, path), difference))
+            });
+            difference.or_else(|| {
+                (l.len() != r.len())
+                    .then(|| (String::new(), format!("length {} != {}", l.len(), r.len())))
+            })
+        }
+        (ASTNode::Map(l), ASTNode::Map(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            let mut keys: Vec<&String> = l.keys().chain(r.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            keys.into_iter().find_map(|key| {
+                let (path, difference) = match (l.get(key), r.get(key)) {
+                    (Some(l), Some(r)) => first_difference(l, r, open)?,
+                    (Some(_), None) => (String::new(), "missing from the second value".into()),
+                    _ => (String::new(), "missing from the first value".into()),
+                };
+                Some((format!("{}{}", key_path(key), path), difference))
+            })
+        }
+        _ if values_equal(left, right) => None,
+        _ => Some((
+            String::new(),
+            format!("{} != {}", format_element(left), format_element(right)),
+        )),
+    };
+    if pair.is_some() {
+        open.pop();
+    }
+    difference
+}
+
+/// The path segment for a map key: `.name`, or `["two words"]` when the
+/// key is not a plain name.
+fn key_path(key: &str) -> String {
+    let mut chars = key.chars();
+    let plain = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        format!(".{}", key)
+    } else {
+        format!("[{:?}]", key)
+    }
+}
+
+fn call_function(
+    function: ASTNode,
+    args: Vec<ASTNode>,
+    interp: &mut Interpreter,
+) -> Result<ASTNode, RuntimeError> {
+    if let ASTNode::Memoized { function, cache } = function {
+        let key = args.iter().map(format_element).collect::<Vec<_>>().join(", ");
+        if let Some(value) = cache.borrow().get(&key).cloned() {
+            return Ok(value);
+        }
+        let value = call_function(*function, args, interp)?;
+        cache.borrow_mut().insert(key, value.clone());
+        return Ok(value);
+    }
+    let ASTNode::FunctionDeclaration { name, parameters, body } = function else {
+        return Err(format!("Cannot call a value of type {}", type_name(&function)).into());
+    };
+    let mut args = args;
+    loop {
+        if args.len() != parameters.len() {
+            return Err(format!("Incorrect number of arguments for function '{}'", name).into());
+        }
+        if let Some(call_counts) = &mut interp.call_counts {
+            *call_counts.entry(name.clone()).or_insert(0) += 1;
+        }
+        let local_env = Environment::new_function(interp.globals.clone());
+        for (param, arg_value) in parameters.iter().zip(args) {
+            local_env.define(param.clone(), arg_value);
+        }
+        let previous_function = interp.current_function.replace(name.clone());
+        let previous_try_depth = std::mem::replace(&mut interp.try_depth, 0);
+        let previous_defers = std::mem::replace(&mut interp.pending_defers, 0);
+        let flow = execute_block(&body, &local_env, interp);
+        interp.current_function = previous_function;
+        interp.try_depth = previous_try_depth;
+        interp.pending_defers = previous_defers;
+        match flow? {
+            ControlFlow::Return(value) => return Ok(value),
+            // The parser keeps `break` and `continue` inside loops in the
+            // body, and `stop` out of it
+            ControlFlow::Normal
+            | ControlFlow::Break(_)
+            | ControlFlow::Continue(_)
+            | ControlFlow::Stop => {
+                return Ok(ASTNode::NumberLiteral(0.0))
+            }
+            // Self tail call: rebind the parameters and run the body again
+            ControlFlow::TailCall(next_args) => args = next_args,
+        }
+    }
+}
+
+/// Whether `expr` (the value of a `return`) is a call to the function that is
+/// currently executing, in a position where it can be turned into a loop.
+fn is_self_tail_call(expr: &ASTNode, env: &Environment, interp: &Interpreter) -> bool {
+    let ASTNode::FunctionCall { name, .. } = expr else {
+        return false;
+    };
+    interp.try_depth == 0
+        && interp.pending_defers == 0
+        && interp.current_function.as_deref() == Some(&**name)
+        && env.get(name).is_none()
+}
+
+/// Every built-in function with the minimum and maximum number of
+/// arguments it accepts.
+const BUILTINS: &[(&str, usize, usize)] = &[
+    ("abs", 1, 1),
+    ("assert_eq", 2, 2),
+    ("bin", 1, 1),
+    ("chars", 1, 1),
+    ("clamp", 3, 3),
+    ("copy", 1, 1),
+    ("cos", 1, 1),
+    ("count", 2, 2),
+    ("debug", 0, 0),
+    ("diff", 2, 2),
+    ("ends_with", 2, 2),
+    ("eval", 1, 1),
+    ("exp", 1, 1),
+    ("find", 2, 2),
+    ("find_index", 2, 2),
+    ("flatten", 1, 1),
+    ("flatten_deep", 1, 1),
+    ("fmt", 1, usize::MAX),
+    ("format_sci", 2, 2),
+    ("functions", 0, 0),
+    ("group_by", 2, 2),
+    ("hex", 1, 1),
+    ("is_inf", 1, 1),
+    ("is_int", 1, 1),
+    ("is_nan", 1, 1),
+    ("join", 2, 2),
+    ("len", 1, 1),
+    ("log", 1, 1),
+    ("log10", 1, 1),
+    ("max_of", 1, 1),
+    ("memoize", 1, 1),
+    ("min_of", 1, 1),
+    ("oct", 1, 1),
+    ("pad_left", 2, 3),
+    ("pad_right", 2, 3),
+    ("panic", 1, 1),
+    ("parse_json", 1, 1),
+    ("product", 1, 1),
+    ("range", 1, 3),
+    ("read_all", 0, 0),
+    ("read_lines", 0, 0),
+    ("replace", 3, 3),
+    ("repr", 1, 1),
+    ("reverse", 1, 1),
+    ("round_to", 2, 2),
+    ("script_dir", 0, 0),
+    ("script_path", 0, 0),
+    ("set_numeric_guard", 1, 1),
+    ("set_thousands", 1, 1),
+    ("sign", 1, 1),
+    ("sin", 1, 1),
+    ("sort", 1, 2),
+    ("starts_with", 2, 2),
+    ("sum", 1, 1),
+    ("tan", 1, 1),
+    ("to_json", 1, 1),
+    ("to_map", 1, 1),
+    ("to_pairs", 1, 1),
+    ("typeof", 1, 1),
+    ("uid", 0, 0),
+    ("unique", 1, 1),
+    ("upper", 1, 1),
+    ("zip", 2, 2),
+];
+
+fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, ..)| *builtin == name)
+}
+
+/// Validates the argument count of a built-in call, producing a uniform
+/// message such as "len() expects 1 argument, got 2".
+fn check_arity(name: &str, count: usize) -> Result<(), String> {
+    let Some(&(_, min, max)) = BUILTINS.iter().find(|(builtin, ..)| *builtin == name) else {
+        return Ok(());
+    };
+    if (min..=max).contains(&count) {
+        return Ok(());
+    }
+    let expected = if min == max {
+        format!("{} argument{}", min, if min == 1 { "" } else { "s" })
+    } else if max == usize::MAX {
+        format!("at least {} argument{}", min, if min == 1 { "" } else { "s" })
+    } else {
+        format!("{} to {} arguments", min, max)
+    };
+    Err(format!("{}() expects {}, got {}", name, expected, count))
+}
+
+/// Calls the built-in `name` with already-evaluated arguments. Method calls
+/// such as `s.upper()` land here too, with the receiver as the first argument.
+fn call_builtin(
+    name: &str,
+    args: Vec<ASTNode>,
+    env: &Environment,
+    interp: &mut Interpreter,
+) -> Result<ASTNode, RuntimeError> {
+    check_arity(name, args.len())?;
+    match (name, args.as_slice()) {
+        ("abs", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::NumberLiteral(n.abs())),
+        ("assert_eq", [actual, expected]) => {
+            if let Some((path, difference)) = first_difference(actual, expected, &mut Vec::new()) {
+                let mut message = format!(
+                    "assert_eq failed: expected {}, got {}",
+                    format_element(expected),
+                    format_element(actual)
+                );
+                // Point into collections, where the mismatch can be hard to spot
+                if !path.is_empty() {
+                    message.push_str(&format!(" (at {}: {})", path, difference));
+                }
+                return Err(message.into());
+            }
+            Ok(ASTNode::NumberLiteral(0.0))
+        }
+        ("chars", [ASTNode::StringLiteral(s)]) => {
+            let chars = s.chars().map(|c| ASTNode::StringLiteral(c.to_string())).collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(chars))))
+        }
+        (
+            "clamp",
+            [ASTNode::NumberLiteral(n), ASTNode::NumberLiteral(lo), ASTNode::NumberLiteral(hi)],
+        ) => {
+            if lo.is_nan() || hi.is_nan() {
+                return Err("clamp() bounds cannot be NaN".into());
+            }
+            if lo > hi {
+                return Err(format!(
+                    "clamp() lower bound {} is greater than upper bound {}",
+                    format_number(*lo, false),
+                    format_number(*hi, false)
+                )
+                .into());
+            }
+            Ok(ASTNode::NumberLiteral(n.clamp(*lo, *hi)))
+        }
+        ("copy", [value]) => Ok(deep_copy(value)),
+        ("count", [ASTNode::StringLiteral(_), ASTNode::StringLiteral(needle)]) if needle.is_empty() => {
+            Err("count() needle cannot be empty".into())
+        }
+        ("count", [ASTNode::StringLiteral(s), ASTNode::StringLiteral(needle)]) => {
+            Ok(ASTNode::NumberLiteral(s.matches(needle.as_str()).count() as f64))
+        }
+        ("count", [ASTNode::Array(elements), item]) => {
+            let count = elements.borrow().iter().filter(|element| values_equal(element, item)).count();
+            Ok(ASTNode::NumberLiteral(count as f64))
+        }
+        ("debug", []) => {
+            eprintln!("[debug] variables in scope:");
+            for (name, value) in env.bindings() {
+                eprintln!("[debug]   {} = {}", name, format_element(&value));
+            }
+            // Only pause when someone is there to resume the program
+            if io::stdin().is_terminal() {
+                eprint!("[debug] press enter to continue...");
+                let mut line = String::new();
+                io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
+            }
+            Ok(ASTNode::NumberLiteral(0.0))
+        }
+        ("cos", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::NumberLiteral(n.cos())),
+        ("ends_with", [ASTNode::StringLiteral(s), ASTNode::StringLiteral(suffix)]) => {
+            Ok(ASTNode::BooleanLiteral(s.ends_with(suffix.as_str())))
+        }
+        ("flatten", [ASTNode::Array(elements)]) => {
+            let mut flat = Vec::new();
+            for element in elements.borrow().iter() {
+                match element {
+                    ASTNode::Array(inner) => flat.extend(inner.borrow().iter().cloned()),
+                    other => flat.push(other.clone()),
+                }
+            }
+            Ok(ASTNode::Array(Rc::new(RefCell::new(flat))))
+        }
+        ("flatten_deep", [ASTNode::Array(elements)]) => {
+            let mut flat = Vec::new();
+            let mut open = vec![Rc::as_ptr(elements).cast()];
+            flatten_into(&elements.borrow(), &mut open, &mut flat)?;
+            Ok(ASTNode::Array(Rc::new(RefCell::new(flat))))
+        }
+        ("fmt", [ASTNode::StringLiteral(template), values @ ..]) => {
+            Ok(ASTNode::StringLiteral(format_template(template, values)?))
+        }
+        ("format_sci", [ASTNode::NumberLiteral(n), ASTNode::NumberLiteral(digits)]) => {
+            if digits.fract() != 0.0 || *digits < 0.0 {
+                return Err("format_sci() digits must be a non-negative integer".into());
+            }
+            Ok(ASTNode::StringLiteral(format!("{:.*e}", *digits as usize, n)))
+        }
+        ("diff", [left, right]) => Ok(match first_difference(left, right, &mut Vec::new()) {
+            None => ASTNode::NilLiteral,
+            Some((path, difference)) if path.is_empty() => ASTNode::StringLiteral(difference),
+            Some((path, difference)) => {
+                ASTNode::StringLiteral(format!("{}: {}", path, difference))
+            }
+        }),
+        ("eval", [ASTNode::StringLiteral(source)]) => eval_source(source, env, interp),
+        ("exp", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::NumberLiteral(n.exp())),
+        ("find" | "find_index", [ASTNode::Array(elements), predicate]) => {
+            // Snapshot the elements so the predicate may modify the array
+            let elements = elements.borrow().clone();
+            for (i, element) in elements.into_iter().enumerate() {
+                if is_truthy(&call_function(predicate.clone(), vec![element.clone()], interp)?) {
+                    return Ok(if name == "find" {
+                        element
+                    } else {
+                        ASTNode::NumberLiteral(i as f64)
+                    });
+                }
+            }
+            Ok(if name == "find" { ASTNode::NilLiteral } else { ASTNode::NumberLiteral(-1.0) })
+        }
+        ("functions", []) => {
+            // Declared functions plus variables that hold function values
+            let mut names: Vec<Rc<str>> = interp.functions.keys().cloned().collect();
+            for (name, value) in env.bindings() {
+                let is_function =
+                    matches!(value, ASTNode::FunctionDeclaration { .. } | ASTNode::Memoized { .. });
+                if is_function && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            let names = names.iter().map(|name| ASTNode::StringLiteral(name.to_string())).collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(names))))
+        }
+        ("bin" | "hex" | "oct", [ASTNode::NumberLiteral(n)]) => {
+            // Limited to what fits in a u64 rather than picking a two's-complement width
+            if n.fract() != 0.0 || *n < 0.0 || *n >= u64::MAX as f64 {
+                return Err(format!(
+                    "{}() requires a non-negative integer, got {}",
+                    name,
+                    format_number(*n, false)
+                )
+                .into());
+            }
+            let n = *n as u64;
+            Ok(ASTNode::StringLiteral(match name {
+                "bin" => format!("{:#b}", n),
+                "hex" => format!("{:#x}", n),
+                _ => format!("{:#o}", n),
+            }))
+        }
+        ("group_by", [ASTNode::Array(elements), function]) => {
+            // Snapshot the elements so the key function may modify the array
+            let elements = elements.borrow().clone();
+            let mut groups: HashMap<String, Vec<ASTNode>> = HashMap::new();
+            for element in elements {
+                let key = call_function(function.clone(), vec![element.clone()], interp)?;
+                let key = map_key(&key)
+                    .map_err(|_| "group_by() keys must be strings or numbers".to_string())?;
+                groups.entry(key).or_default().push(element);
+            }
+            let groups = groups
+                .into_iter()
+                .map(|(key, group)| (key, ASTNode::Array(Rc::new(RefCell::new(group)))))
+                .collect();
+            Ok(ASTNode::Map(Rc::new(RefCell::new(groups))))
+        }
+        ("is_inf", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::BooleanLiteral(n.is_infinite())),
+        ("is_int", [ASTNode::NumberLiteral(n)]) => {
+            Ok(ASTNode::BooleanLiteral(n.is_finite() && n.fract() == 0.0))
+        }
+        ("is_nan", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::BooleanLiteral(n.is_nan())),
+        ("join", [ASTNode::Array(elements), ASTNode::StringLiteral(separator)]) => {
+            let parts: Vec<String> = elements.borrow().iter().map(format_value).collect();
+            Ok(ASTNode::StringLiteral(parts.join(separator)))
+        }
+        ("len", [ASTNode::StringLiteral(s)]) => {
+            Ok(ASTNode::NumberLiteral(s.chars().count() as f64))
+        }
+        ("len", [ASTNode::Array(elements)]) => {
+            Ok(ASTNode::NumberLiteral(elements.borrow().len() as f64))
+        }
+        ("len", [ASTNode::Map(entries)]) => {
+            Ok(ASTNode::NumberLiteral(entries.borrow().len() as f64))
+        }
+        ("log" | "log10", [ASTNode::NumberLiteral(n)]) => {
+            // Rather than returning NaN or -inf outside the domain, which
+            // includes NaN itself
+            if *n <= 0.0 || n.is_nan() {
+                return Err(format!(
+                    "{}() requires a positive number, got {}",
+                    name,
+                    format_number(*n, false)
+                )
+                .into());
+            }
+            Ok(ASTNode::NumberLiteral(if name == "log" { n.ln() } else { n.log10() }))
+        }
+        ("max_of" | "min_of", [ASTNode::Array(elements)]) => {
+            // Ordered as by `sort`, so the elements must be all numbers or all strings
+            let elements = elements.borrow();
+            let Some(mut best) = elements.first() else {
+                return Err(format!("{}() of an empty array", name).into());
+            };
+            compare_values(best, best)?;
+            let wanted = if name == "max_of" { Ordering::Greater } else { Ordering::Less };
+            for element in &elements[1..] {
+                if compare_values(element, best)? == wanted {
+                    best = element;
+                }
+            }
+            Ok(best.clone())
+        }
+        ("memoize", [function @ ASTNode::FunctionDeclaration { .. }]) => Ok(ASTNode::Memoized {
+            function: Box::new(function.clone()),
+            cache: Rc::new(RefCell::new(HashMap::new())),
+        }),
+        (
+            "pad_left" | "pad_right",
+            [ASTNode::StringLiteral(s), ASTNode::NumberLiteral(width), fill @ ..],
+        ) if matches!(fill, [] | [ASTNode::StringLiteral(_)]) => {
+            if width.fract() != 0.0 || *width < 0.0 {
+                return Err(format!("{}() width must be a non-negative integer", name).into());
+            }
+            let fill = match fill {
+                [ASTNode::StringLiteral(fill)] => {
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => {
+                            let message = format!("{}() fill must be a single character", name);
+                            return Err(message.into());
+                        }
+                    }
+                }
+                _ => ' ',
+            };
+            // Widths count characters, as `len` does
+            let padding: String =
+                std::iter::repeat_n(fill, (*width as usize).saturating_sub(s.chars().count()))
+                    .collect();
+            Ok(ASTNode::StringLiteral(if name == "pad_left" {
+                padding + s
             } else {
-                eprintln!("Error: Undefined function '{}'", name);
-                ASTNode::NumberLiteral(0.0)
+                s.clone() + &padding
+            }))
+        }
+        // Never returns normally; the message propagates up to `main`
+        ("panic", [message]) => Err(format_value(message).into()),
+        ("parse_json", [ASTNode::StringLiteral(s)]) => Ok(json::parse_json(s)?),
+        ("range", bounds) => {
+            let (start, end, step) = range_bounds(bounds)?;
+            let values = Iteration::Range { next: start, end, step }.collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(values))))
+        }
+        ("read_all", []) => {
+            let input = io::read_to_string(io::stdin()).map_err(|e| e.to_string())?;
+            Ok(ASTNode::StringLiteral(input))
+        }
+        ("read_lines", []) => {
+            let input = io::read_to_string(io::stdin()).map_err(|e| e.to_string())?;
+            let lines = input.lines().map(|line| ASTNode::StringLiteral(line.to_string())).collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(lines))))
+        }
+        (
+            "replace",
+            [ASTNode::StringLiteral(s), ASTNode::StringLiteral(from), ASTNode::StringLiteral(to)],
+        ) => {
+            if from.is_empty() {
+                return Err("replace() cannot replace an empty string".into());
             }
+            Ok(ASTNode::StringLiteral(s.replace(from.as_str(), to)))
         }
-        ASTNode::UnaryExpression { operator, operand } => {
-            let operand_value = evaluate(*operand, env, functions);
-            match operand_value {
-                ASTNode::NumberLiteral(n) => match operator {
-                    Token::Minus => ASTNode::NumberLiteral(-n),
-                    Token::Bang => ASTNode::NumberLiteral((n == 0.0) as i32 as f64),
-                    _ => {
-                        eprintln!("Error: Unsupported unary operator");
-                        ASTNode::NumberLiteral(0.0)
+        ("repr", [value]) => Ok(ASTNode::StringLiteral(format_element(value))),
+        ("reverse", [ASTNode::Array(elements)]) => {
+            let reversed = elements.borrow().iter().rev().cloned().collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(reversed))))
+        }
+        ("reverse", [ASTNode::StringLiteral(s)]) => {
+            Ok(ASTNode::StringLiteral(s.chars().rev().collect()))
+        }
+        ("round_to", [ASTNode::NumberLiteral(n), ASTNode::NumberLiteral(places)]) => {
+            if places.fract() != 0.0 || *places < 0.0 {
+                return Err("round_to() places must be a non-negative integer".into());
+            }
+            let scale = 10f64.powf(*places);
+            let rounded = (n * scale).round() / scale;
+            // Past f64 precision the scaling overflows; the value is already exact enough
+            Ok(ASTNode::NumberLiteral(if rounded.is_finite() { rounded } else { *n }))
+        }
+        ("script_dir", []) => {
+            let dir = interp.script_path.as_ref().and_then(|path| path.parent());
+            Ok(dir.map_or(ASTNode::NilLiteral, |dir| {
+                ASTNode::StringLiteral(dir.to_string_lossy().into_owned())
+            }))
+        }
+        ("script_path", []) => Ok(interp.script_path.as_ref().map_or(ASTNode::NilLiteral, |path| {
+            ASTNode::StringLiteral(path.to_string_lossy().into_owned())
+        })),
+        ("set_numeric_guard", [ASTNode::BooleanLiteral(enabled)]) => {
+            interp.numeric_guard = *enabled;
+            Ok(ASTNode::BooleanLiteral(*enabled))
+        }
+        ("set_thousands", [ASTNode::BooleanLiteral(enabled)]) => {
+            interp.thousands_separators = *enabled;
+            Ok(ASTNode::BooleanLiteral(*enabled))
+        }
+        ("sign", [ASTNode::NumberLiteral(n)]) => {
+            // Zero (of either sign) and NaN are their own sign
+            let sign = if *n > 0.0 { 1.0 } else if *n < 0.0 { -1.0 } else { *n };
+            Ok(ASTNode::NumberLiteral(sign))
+        }
+        ("sin", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::NumberLiteral(n.sin())),
+        ("sort", [ASTNode::Array(elements)]) => {
+            let sorted = merge_sort(elements.borrow().clone(), &mut compare_values)?;
+            Ok(ASTNode::Array(Rc::new(RefCell::new(sorted))))
+        }
+        ("sort", [ASTNode::Array(elements), comparator]) => {
+            let mut compare = |a: &ASTNode, b: &ASTNode| -> Result<Ordering, RuntimeError> {
+                let args = vec![a.clone(), b.clone()];
+                match call_function(comparator.clone(), args, interp)? {
+                    ASTNode::NumberLiteral(n) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                    _ => Err("sort() comparator must return a number".into()),
+                }
+            };
+            // Snapshot the elements so the comparator may modify the array
+            let elements = elements.borrow().clone();
+            let sorted = merge_sort(elements, &mut compare)?;
+            Ok(ASTNode::Array(Rc::new(RefCell::new(sorted))))
+        }
+        ("starts_with", [ASTNode::StringLiteral(s), ASTNode::StringLiteral(prefix)]) => {
+            Ok(ASTNode::BooleanLiteral(s.starts_with(prefix.as_str())))
+        }
+        ("sum" | "product", [ASTNode::Array(elements)]) => {
+            let mut numbers = Vec::new();
+            for (i, element) in elements.borrow().iter().enumerate() {
+                match element {
+                    ASTNode::NumberLiteral(n) => numbers.push(*n),
+                    _ => return Err(format!("{}() element {} is not a number", name, i).into()),
+                }
+            }
+            Ok(ASTNode::NumberLiteral(if name == "sum" {
+                numbers.iter().sum()
+            } else {
+                numbers.iter().product()
+            }))
+        }
+        ("tan", [ASTNode::NumberLiteral(n)]) => Ok(ASTNode::NumberLiteral(n.tan())),
+        ("to_json", [value]) => Ok(ASTNode::StringLiteral(json::to_json(value)?)),
+        ("to_map", [ASTNode::Array(pairs)]) => {
+            let mut entries = HashMap::new();
+            for (i, pair) in pairs.borrow().iter().enumerate() {
+                let pair = match pair {
+                    ASTNode::Array(pair) => pair.borrow().clone(),
+                    _ => Vec::new(),
+                };
+                let [key, value] = pair.as_slice() else {
+                    return Err(format!("to_map() element {} is not a [key, value] pair", i).into());
+                };
+                entries.insert(map_key(key)?, value.clone());
+            }
+            Ok(ASTNode::Map(Rc::new(RefCell::new(entries))))
+        }
+        ("to_pairs", [ASTNode::Map(entries)]) => {
+            let entries = entries.borrow();
+            let mut keys: Vec<&String> = entries.keys().collect();
+            keys.sort();
+            let pairs = keys
+                .into_iter()
+                .map(|key| {
+                    let pair = vec![ASTNode::StringLiteral(key.clone()), entries[key].clone()];
+                    ASTNode::Array(Rc::new(RefCell::new(pair)))
+                })
+                .collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(pairs))))
+        }
+        ("typeof", [value]) => Ok(ASTNode::StringLiteral(type_name(value).to_string())),
+        ("uid", []) => {
+            interp.uid_count += 1;
+            Ok(ASTNode::StringLiteral(format!("{:016x}-{}", interp.uid_seed, interp.uid_count)))
+        }
+        ("unique", [ASTNode::Array(elements)]) => {
+            // Compared with `==`, so equal nested collections count as duplicates
+            let mut unique: Vec<ASTNode> = Vec::new();
+            for element in elements.borrow().iter() {
+                if !unique.iter().any(|seen| values_equal(seen, element)) {
+                    unique.push(element.clone());
+                }
+            }
+            Ok(ASTNode::Array(Rc::new(RefCell::new(unique))))
+        }
+        ("upper", [ASTNode::StringLiteral(s)]) => Ok(ASTNode::StringLiteral(s.to_uppercase())),
+        ("zip", [ASTNode::Array(left), ASTNode::Array(right)]) => {
+            // Pairs stop at the end of the shorter array
+            let pairs = left
+                .borrow()
+                .iter()
+                .zip(right.borrow().iter())
+                .map(|(a, b)| ASTNode::Array(Rc::new(RefCell::new(vec![a.clone(), b.clone()]))))
+                .collect();
+            Ok(ASTNode::Array(Rc::new(RefCell::new(pairs))))
+        }
+        _ => Err(argument_type_error(name, &args)),
+    }
+}
+
+/// Reads the arguments of `range(end)`, `range(start, end)` or
+/// `range(start, end, step)` as `(start, end, step)`. The range runs up to
+/// but excluding `end`; a negative step counts down.
+fn range_bounds(args: &[ASTNode]) -> Result<(f64, f64, f64), RuntimeError> {
+    check_arity("range", args.len())?;
+    let (start, end, step) = match args {
+        [ASTNode::NumberLiteral(end)] => (0.0, *end, 1.0),
+        [ASTNode::NumberLiteral(start), ASTNode::NumberLiteral(end)] => (*start, *end, 1.0),
+        [ASTNode::NumberLiteral(start), ASTNode::NumberLiteral(end), ASTNode::NumberLiteral(step)] => {
+            (*start, *end, *step)
+        }
+        _ => return Err(argument_type_error("range", args)),
+    };
+    if [start, end, step].iter().any(|n| n.fract() != 0.0) {
+        return Err("range() arguments must be integers".into());
+    }
+    if step == 0.0 {
+        return Err("range() step cannot be zero".into());
+    }
+    Ok((start, end, step))
+}
+
+fn argument_type_error(name: &str, args: &[ASTNode]) -> RuntimeError {
+    let types: Vec<&str> = args.iter().map(type_name).collect();
+    format!("{}() does not accept arguments of type ({})", name, types.join(", ")).into()
+}
+
+/// Appends the non-array leaves of `elements` to `flat`, recursing into
+/// nested arrays of any depth. `open` holds the arrays being flattened, so
+/// one that contains itself is an error rather than endless.
+fn flatten_into(
+    elements: &[ASTNode],
+    open: &mut Vec<*const ()>,
+    flat: &mut Vec<ASTNode>,
+) -> Result<(), String> {
+    for element in elements {
+        match element {
+            ASTNode::Array(inner) => {
+                let ptr = Rc::as_ptr(inner).cast();
+                if open.contains(&ptr) {
+                    return Err("flatten_deep() of an array that contains itself".into());
+                }
+                open.push(ptr);
+                flatten_into(&inner.borrow(), open, flat)?;
+                open.pop();
+            }
+            other => flat.push(other.clone()),
+        }
+    }
+    Ok(())
+}
+
+/// Default ordering for `sort`: numbers numerically, strings lexicographically.
+fn compare_values(a: &ASTNode, b: &ASTNode) -> Result<Ordering, String> {
+    match (a, b) {
+        (ASTNode::NumberLiteral(l), ASTNode::NumberLiteral(r)) => {
+            // NaN has no order of its own; sort it after every number
+            Ok(l.partial_cmp(r).unwrap_or_else(|| l.is_nan().cmp(&r.is_nan())))
+        }
+        (ASTNode::StringLiteral(l), ASTNode::StringLiteral(r)) => Ok(l.cmp(r)),
+        _ => Err(format!("Cannot compare {} with {}", type_name(a), type_name(b))),
+    }
+}
+
+/// A stable merge sort with a fallible comparator. Unlike `slice::sort_by`,
+/// it tolerates comparators that are not a consistent total order.
+fn merge_sort<E>(
+    mut values: Vec<ASTNode>,
+    compare: &mut dyn FnMut(&ASTNode, &ASTNode) -> Result<Ordering, E>,
+) -> Result<Vec<ASTNode>, E> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, compare)?;
+    let right = merge_sort(right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if compare(r, l)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Resolves an index value against an array or string of `len` elements,
+/// named by `kind` in errors. Negative indices count back from the end.
+fn sequence_index(index: &ASTNode, len: usize, kind: &str) -> Result<usize, String> {
+    let n = match index {
+        ASTNode::NumberLiteral(n) if n.fract() == 0.0 => *n,
+        _ => return Err(format!("{} index must be an integer", kind)),
+    };
+    let i = if n < 0.0 { len as f64 + n } else { n };
+    if i < 0.0 || i >= len as f64 {
+        return Err(format!("{} index {} out of bounds", kind, n));
+    }
+    Ok(i as usize)
+}
+
+/// Resolves optional slice bounds against a sequence of `len` elements.
+/// Negative bounds count back from the end, and out-of-range bounds are
+/// clamped rather than reported, so `[5, 6][1:10]` is simply `[6]`.
+fn slice_bounds(
+    start: Option<&ASTNode>,
+    end: Option<&ASTNode>,
+    len: usize,
+) -> Result<(usize, usize), String> {
+    let resolve = |bound: Option<&ASTNode>, default: usize| match bound {
+        None => Ok(default),
+        Some(ASTNode::NumberLiteral(n)) if n.fract() == 0.0 => {
+            let i = if *n < 0.0 { len as f64 + n } else { *n };
+            Ok(i.clamp(0.0, len as f64) as usize)
+        }
+        Some(_) => Err("Slice bounds must be integers".to_string()),
+    };
+    let start = resolve(start, 0)?;
+    let end = resolve(end, len)?;
+    Ok((start, end.max(start)))
+}
+
+/// Map keys are stored as strings; numbers are converted to their printed form.
+fn map_key(key: &ASTNode) -> Result<String, String> {
+    match key {
+        ASTNode::StringLiteral(s) => Ok(s.clone()),
+        ASTNode::NumberLiteral(n) => Ok(format_number(*n, false)),
+        _ => Err("Map keys must be strings or numbers".into()),
+    }
+}
+
+fn deep_copy(value: &ASTNode) -> ASTNode {
+    copy_within(value, &mut Vec::new())
+}
+
+/// Copies a value, given each collection it is nested in paired with its
+/// copy. A collection that contains itself yields a copy that contains the
+/// copy, rather than copying forever.
+fn copy_within(value: &ASTNode, open: &mut Vec<(*const (), ASTNode)>) -> ASTNode {
+    if let Some(ptr) = collection_ptr(value) {
+        if let Some((_, copy)) = open.iter().find(|(open, _)| *open == ptr) {
+            return copy.clone();
+        }
+    }
+    match value {
+        ASTNode::Array(elements) => {
+            let copy = Rc::new(RefCell::new(Vec::new()));
+            open.push((Rc::as_ptr(elements).cast(), ASTNode::Array(copy.clone())));
+            let copied =
+                elements.borrow().iter().map(|element| copy_within(element, open)).collect();
+            open.pop();
+            *copy.borrow_mut() = copied;
+            ASTNode::Array(copy)
+        }
+        ASTNode::Map(entries) => {
+            let copy = Rc::new(RefCell::new(HashMap::new()));
+            open.push((Rc::as_ptr(entries).cast(), ASTNode::Map(copy.clone())));
+            let copied = entries
+                .borrow()
+                .iter()
+                .map(|(key, value)| (key.clone(), copy_within(value, open)))
+                .collect();
+            open.pop();
+            *copy.borrow_mut() = copied;
+            ASTNode::Map(copy)
+        }
+        _ => value.clone(),
+    }
+}
+
+fn format_value(value: &ASTNode) -> String {
+    format_within(value, &mut Vec::new())
+}
+
+/// Formats a value, given the collections it is nested in. A collection
+/// that contains itself shows the inner occurrence as `[...]` or `{...}`.
+fn format_within(value: &ASTNode, open: &mut Vec<*const ()>) -> String {
+    let ptr = collection_ptr(value);
+    if let Some(ptr) = ptr {
+        if open.contains(&ptr) {
+            let nested = if matches!(value, ASTNode::Map(_)) { "{...}" } else { "[...]" };
+            return nested.to_string();
+        }
+        open.push(ptr);
+    }
+    let formatted = match value {
+        ASTNode::StringLiteral(s) => s.clone(),
+        ASTNode::NumberLiteral(n) => format_number(*n, false),
+        ASTNode::BooleanLiteral(b) => b.to_string(),
+        ASTNode::NilLiteral => "nil".to_string(),
+        ASTNode::FunctionDeclaration { name, .. } => format!("<func {}>", name),
+        ASTNode::Memoized { function, .. } => format_within(function, open),
+        ASTNode::Array(elements) => {
+            let parts: Vec<String> =
+                elements.borrow().iter().map(|element| element_within(element, open)).collect();
+            format!("[{}]", parts.join(", "))
+        }
+        ASTNode::Map(entries) => {
+            let entries = entries.borrow();
+            let mut keys: Vec<&String> = entries.keys().collect();
+            keys.sort();
+            let parts: Vec<String> = keys
+                .into_iter()
+                .map(|key| format!("{:?}: {}", key, element_within(&entries[key], open)))
+                .collect();
+            format!("{{{}}}", parts.join(", "))
+        }
+        _ => String::new(),
+    };
+    if ptr.is_some() {
+        open.pop();
+    }
+    formatted
+}
+
+/// Expands the placeholders of a `fmt` template with the printed form of
+/// `values`. `{}` takes the values in order while `{0}`, `{1}`, ... pick one
+/// by index, so it can be reused; a template uses one style or the other.
+/// `{{` and `}}` stand for literal braces.
+fn format_template(template: &str, values: &[ASTNode]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut indexed = false;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                if chars.next() != Some('}') {
+                    return Err("fmt() placeholders must be written '{}' or '{N}'".into());
+                }
+                if (digits.is_empty() && indexed) || (!digits.is_empty() && next > 0) {
+                    return Err("fmt() cannot mix '{}' and '{N}' placeholders".into());
+                }
+                if digits.is_empty() {
+                    if let Some(value) = values.get(next) {
+                        out.push_str(&format_value(value));
                     }
-                },
-                _ => {
-                    eprintln!("Error: Invalid operand for unary operator");
-                    ASTNode::NumberLiteral(0.0)
+                    next += 1;
+                } else {
+                    indexed = true;
+                    let value = digits.parse().ok().and_then(|i: usize| values.get(i));
+                    let Some(value) = value else {
+                        return Err(format!(
+                            "fmt() placeholder {{{}}} is out of range for {} value{}",
+                            digits,
+                            values.len(),
+                            if values.len() == 1 { "" } else { "s" }
+                        ));
+                    };
+                    out.push_str(&format_value(value));
                 }
             }
+            '}' => return Err("fmt() template has an unmatched '}'".into()),
+            c => out.push(c),
         }
-        _ => {
-            eprintln!("Error: Unsupported AST node in evaluation");
-            ASTNode::NumberLiteral(0.0)
+    }
+    if !indexed && next != values.len() {
+        return Err(format!(
+            "fmt() template has {} placeholder{} but {} value{} given",
+            next,
+            if next == 1 { "" } else { "s" },
+            values.len(),
+            if values.len() == 1 { " was" } else { "s were" }
+        ));
+    }
+    Ok(out)
+}
+
+/// Formats a number for `print`, optionally grouping the digits of its
+/// integer part with thousands separators (`1234567` becomes `1,234,567`).
+fn format_number(n: f64, thousands_separators: bool) -> String {
+    // Negative zero equals zero, so it prints as plain `0`
+    let n = if n == 0.0 { 0.0 } else { n };
+    let formatted = n.to_string();
+    if !thousands_separators || !n.is_finite() {
+        return formatted;
+    }
+    let (sign, unsigned) = match formatted.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", formatted.as_str()),
+    };
+    let (integer, fraction) = match unsigned.find('.') {
+        Some(dot) => unsigned.split_at(dot),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
         }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+/// Like `format_value`, but quotes and escapes strings (as Rust's `{:?}` does)
+/// so they stand out inside collections and in `repr`.
+fn format_element(value: &ASTNode) -> String {
+    element_within(value, &mut Vec::new())
+}
+
+fn element_within(value: &ASTNode, open: &mut Vec<*const ()>) -> String {
+    match value {
+        ASTNode::StringLiteral(s) => format!("{:?}", s),
+        _ => format_within(value, open),
+    }
+}
+
+fn type_name(value: &ASTNode) -> &'static str {
+    match value {
+        ASTNode::NumberLiteral(_) => "number",
+        ASTNode::StringLiteral(_) => "string",
+        ASTNode::BooleanLiteral(_) => "boolean",
+        ASTNode::NilLiteral => "nil",
+        ASTNode::Array(_) => "array",
+        ASTNode::Map(_) => "map",
+        ASTNode::FunctionDeclaration { .. } | ASTNode::Memoized { .. } => "function",
+        _ => "unknown",
     }
 }
 
 fn is_truthy(value: &ASTNode) -> bool {
     match value {
+        ASTNode::BooleanLiteral(b) => *b,
         ASTNode::NumberLiteral(n) => *n != 0.0,
         ASTNode::StringLiteral(s) => !s.is_empty(),
         _ => false,
@@ -0,0 +1,215 @@
+use crate::lexer::Token;
+use crate::parser::ASTNode;
+use crate::{binary_operation, is_truthy};
+
+/// Folds operators whose operands are all literals into a single literal.
+/// Anything that would fail at runtime (such as `1 / 0`) is left in place so
+/// the error is still raised when, and only if, the expression is evaluated.
+pub fn fold_constants(nodes: Vec<ASTNode>) -> Vec<ASTNode> {
+    nodes.into_iter().map(fold).collect()
+}
+
+fn fold(node: ASTNode) -> ASTNode {
+    match node {
+        ASTNode::Program(statements) => ASTNode::Program(fold_constants(statements)),
+        ASTNode::PrintStatement(expr) => ASTNode::PrintStatement(fold_boxed(expr)),
+        ASTNode::VariableAssignment(name, expr) => {
+            ASTNode::VariableAssignment(name, fold_boxed(expr))
+        }
+        ASTNode::LetDeclaration(name, expr) => ASTNode::LetDeclaration(name, fold_boxed(expr)),
+        ASTNode::IfStatement {
+            condition,
+            then_branch,
+            else_branch,
+        } => ASTNode::IfStatement {
+            condition: fold_boxed(condition),
+            then_branch: fold_constants(then_branch),
+            else_branch: else_branch.map(fold_constants),
+        },
+        ASTNode::GuardStatement { condition, else_block } => ASTNode::GuardStatement {
+            condition: fold_boxed(condition),
+            else_block: fold_constants(else_block),
+        },
+        ASTNode::DeferStatement(body) => ASTNode::DeferStatement(fold_constants(body)),
+        ASTNode::WhileStatement { label, condition, body } => ASTNode::WhileStatement {
+            label,
+            condition: fold_boxed(condition),
+            body: fold_constants(body),
+        },
+        ASTNode::RepeatStatement { label, count, body } => ASTNode::RepeatStatement {
+            label,
+            count: fold_boxed(count),
+            body: fold_constants(body),
+        },
+        ASTNode::ForeachStatement { label, index, variable, iterable, body } => {
+            ASTNode::ForeachStatement {
+                label,
+                index,
+                variable,
+                iterable: fold_boxed(iterable),
+                body: fold_constants(body),
+            }
+        }
+        ASTNode::FunctionDeclaration { name, parameters, body } => {
+            let body = fold_constants(body.as_ref().clone());
+            ASTNode::FunctionDeclaration {
+                name,
+                parameters,
+                body: body.into(),
+            }
+        }
+        ASTNode::FunctionCall { name, arguments } => ASTNode::FunctionCall {
+            name,
+            arguments: fold_constants(arguments),
+        },
+        ASTNode::ReturnStatement(expr) => ASTNode::ReturnStatement(expr.map(fold_boxed)),
+        ASTNode::ThrowStatement(expr) => ASTNode::ThrowStatement(fold_boxed(expr)),
+        ASTNode::TryCatch {
+            try_block,
+            error_name,
+            catch_block,
+        } => ASTNode::TryCatch {
+            try_block: fold_constants(try_block),
+            error_name,
+            catch_block: fold_constants(catch_block),
+        },
+        ASTNode::BinaryExpression { left, operator, right } => {
+            let left = fold(*left);
+            let right = fold(*right);
+            if is_literal(&left) && is_literal(&right) {
+                // Non-finite results are left for the runtime numeric guard
+                match binary_operation(&operator, left.clone(), right.clone()) {
+                    Ok(ASTNode::NumberLiteral(n)) if !n.is_finite() => {}
+                    Ok(value) => return value,
+                    Err(_) => {}
+                }
+            }
+            ASTNode::BinaryExpression {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }
+        }
+        ASTNode::UnaryExpression { operator, operand } => match (operator, fold(*operand)) {
+            (Token::Minus, ASTNode::NumberLiteral(n)) => ASTNode::NumberLiteral(-n),
+            (Token::Bang, operand) if is_literal(&operand) => {
+                ASTNode::BooleanLiteral(!is_truthy(&operand))
+            }
+            (operator, operand) => ASTNode::UnaryExpression {
+                operator,
+                operand: Box::new(operand),
+            },
+        },
+        ASTNode::ArrayLiteral(elements) => ASTNode::ArrayLiteral(fold_constants(elements)),
+        ASTNode::MapLiteral(entries) => ASTNode::MapLiteral(
+            entries
+                .into_iter()
+                .map(|(key, value)| (fold(key), fold(value)))
+                .collect(),
+        ),
+        ASTNode::IndexExpression { target, index } => ASTNode::IndexExpression {
+            target: fold_boxed(target),
+            index: fold_boxed(index),
+        },
+        ASTNode::OptionalIndexExpression { target, index } => ASTNode::OptionalIndexExpression {
+            target: fold_boxed(target),
+            index: fold_boxed(index),
+        },
+        ASTNode::ChainedAssignment { targets, value } => ASTNode::ChainedAssignment {
+            targets: fold_constants(targets),
+            value: fold_boxed(value),
+        },
+        ASTNode::ParallelAssignment { targets, values } => ASTNode::ParallelAssignment {
+            targets: fold_constants(targets),
+            values: fold_constants(values),
+        },
+        ASTNode::IndexAssignment { target, index, value } => ASTNode::IndexAssignment {
+            target: fold_boxed(target),
+            index: fold_boxed(index),
+            value: fold_boxed(value),
+        },
+        ASTNode::SliceExpression { target, start, end } => ASTNode::SliceExpression {
+            target: fold_boxed(target),
+            start: start.map(fold_boxed),
+            end: end.map(fold_boxed),
+        },
+        ASTNode::MethodCall { receiver, method, arguments } => ASTNode::MethodCall {
+            receiver: fold_boxed(receiver),
+            method,
+            arguments: fold_constants(arguments),
+        },
+        ASTNode::SequenceExpression(expressions) => {
+            ASTNode::SequenceExpression(fold_constants(expressions))
+        }
+        ASTNode::CallExpression { callee, arguments } => ASTNode::CallExpression {
+            callee: fold_boxed(callee),
+            arguments: fold_constants(arguments),
+        },
+        other => other,
+    }
+}
+
+fn fold_boxed(mut node: Box<ASTNode>) -> Box<ASTNode> {
+    *node = fold(*node);
+    node
+}
+
+fn is_literal(node: &ASTNode) -> bool {
+    matches!(
+        node,
+        ASTNode::NumberLiteral(_) | ASTNode::StringLiteral(_) | ASTNode::BooleanLiteral(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    /// The folded tree of `source`, printed for comparison.
+    fn folded(source: &str) -> String {
+        let (tokens, positions) = lex(source).unwrap();
+        format!("{:?}", fold_constants(parse(&tokens, &positions).unwrap()))
+    }
+
+    #[test]
+    fn literal_arithmetic_becomes_a_single_literal() {
+        assert_eq!(folded("print 1 + 2 * 3;"), "[PrintStatement(NumberLiteral(7.0))]");
+        assert_eq!(folded("print -(2 - 5);"), "[PrintStatement(NumberLiteral(3.0))]");
+        assert_eq!(folded("print \"a\" + \"b\";"), "[PrintStatement(StringLiteral(\"ab\"))]");
+        assert_eq!(folded("print !(1 < 2);"), "[PrintStatement(BooleanLiteral(false))]");
+    }
+
+    #[test]
+    fn only_the_literal_part_of_an_expression_is_folded() {
+        assert_eq!(
+            folded("print x + 2 * 3;"),
+            "[PrintStatement(BinaryExpression { left: Identifier(\"x\"), operator: Plus, \
+             right: NumberLiteral(6.0) })]"
+        );
+    }
+
+    #[test]
+    fn function_bodies_are_folded() {
+        assert_eq!(
+            folded("func f() { return 2 * 3; }"),
+            "[FunctionDeclaration { name: \"f\", parameters: [], \
+             body: [ReturnStatement(Some(NumberLiteral(6.0)))] }]"
+        );
+    }
+
+    #[test]
+    fn expressions_that_would_fail_are_not_folded() {
+        assert_eq!(
+            folded("print 1 / 0;"),
+            "[PrintStatement(BinaryExpression { left: NumberLiteral(1.0), operator: Slash, \
+             right: NumberLiteral(0.0) })]"
+        );
+        assert_eq!(
+            folded("print 1e308 * 10;"),
+            "[PrintStatement(BinaryExpression { left: NumberLiteral(1e308), operator: Star, \
+             right: NumberLiteral(10.0) })]"
+        );
+    }
+}
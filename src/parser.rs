@@ -1,29 +1,100 @@
-use crate::lexer::Token;
+use crate::lexer::{Position, Token};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
 use std::iter::Peekable;
+use std::rc::Rc;
 use std::slice::Iter;
 
 #[derive(Debug, Clone)]
 pub enum ASTNode {
     Program(Vec<ASTNode>),
     PrintStatement(Box<ASTNode>),
-    VariableAssignment(String, Box<ASTNode>),
+    VariableAssignment(Rc<str>, Box<ASTNode>),
+    LetDeclaration(Rc<str>, Box<ASTNode>),
+    /// `global name;`: assignments to `name` in this function update the
+    /// top-level binding.
+    GlobalDeclaration(Rc<str>),
+    /// `import "path";` runs another file's top-level statements;
+    /// `import "path" as name;` keeps its functions under `name.`.
+    ImportStatement {
+        path: String,
+        alias: Option<Rc<str>>,
+    },
     IfStatement {
         condition: Box<ASTNode>,
         then_branch: Vec<ASTNode>,
         else_branch: Option<Vec<ASTNode>>,
     },
-    FunctionDeclaration {
-        name: String,
-        parameters: Vec<String>,
+    /// `guard condition else { ... }` runs the else block, which must end
+    /// by leaving the scope, when the condition is falsy.
+    GuardStatement {
+        condition: Box<ASTNode>,
+        else_block: Vec<ASTNode>,
+    },
+    /// `defer { ... }` runs its block when the enclosing block is left.
+    DeferStatement(Vec<ASTNode>),
+    /// A loop may carry a label, as in `outer: while (...) { ... }`, for
+    /// `break` and `continue` in nested loops to name it.
+    WhileStatement {
+        label: Option<Rc<str>>,
+        condition: Box<ASTNode>,
         body: Vec<ASTNode>,
     },
+    /// `repeat (count) { body }` runs the body `count` times.
+    RepeatStatement {
+        label: Option<Rc<str>>,
+        count: Box<ASTNode>,
+        body: Vec<ASTNode>,
+    },
+    /// `foreach (variable in iterable) { body }`, or
+    /// `foreach (index, variable in iterable) { body }` to also bind the
+    /// zero-based position.
+    ForeachStatement {
+        label: Option<Rc<str>>,
+        index: Option<Rc<str>>,
+        variable: Rc<str>,
+        iterable: Box<ASTNode>,
+        body: Vec<ASTNode>,
+    },
+    FunctionDeclaration {
+        name: Rc<str>,
+        parameters: Vec<Rc<str>>,
+        /// Shared so function values and calls never copy the body.
+        body: Rc<Vec<ASTNode>>,
+    },
     FunctionCall {
-        name: String,
+        name: Rc<str>,
         arguments: Vec<ASTNode>,
     },
-    Identifier(String),
+    ReturnStatement(Option<Box<ASTNode>>),
+    /// `break;` or `break label;`, leaving the innermost loop or the one
+    /// with that label.
+    BreakStatement(Option<Rc<str>>),
+    /// `continue;` or `continue label;`, starting the next iteration of the
+    /// innermost loop or the one with that label.
+    ContinueStatement(Option<Rc<str>>),
+    /// `stop;` ends the program successfully, skipping the statements after
+    /// it (deferred blocks still run). Unlike `return` it is only allowed
+    /// outside functions; in an imported file it ends just that file.
+    StopStatement,
+    ThrowStatement(Box<ASTNode>),
+    TryCatch {
+        try_block: Vec<ASTNode>,
+        error_name: Rc<str>,
+        catch_block: Vec<ASTNode>,
+    },
+    Identifier(Rc<str>),
     StringLiteral(String),
+    /// `tag"text"`: a string whose tag decides, when it is evaluated, what
+    /// value it produces. `json"..."` parses its text as JSON.
+    PrefixedString {
+        tag: Rc<str>,
+        value: String,
+    },
     NumberLiteral(f64),
+    BooleanLiteral(bool),
+    NilLiteral,
     BinaryExpression {
         left: Box<ASTNode>,
         operator: Token,
@@ -33,28 +104,372 @@ pub enum ASTNode {
         operator: Token,
         operand: Box<ASTNode>,
     },
+    ArrayLiteral(Vec<ASTNode>),
+    MapLiteral(Vec<(ASTNode, ASTNode)>),
+    IndexExpression {
+        target: Box<ASTNode>,
+        index: Box<ASTNode>,
+    },
+    /// `target?[index]`: nil when the target is nil instead of an error.
+    OptionalIndexExpression {
+        target: Box<ASTNode>,
+        index: Box<ASTNode>,
+    },
+    /// `a = b = value;`: every target receives the same value.
+    ChainedAssignment {
+        targets: Vec<ASTNode>,
+        value: Box<ASTNode>,
+    },
+    /// `a, b = x, y;`: targets are assigned positionally.
+    ParallelAssignment {
+        targets: Vec<ASTNode>,
+        values: Vec<ASTNode>,
+    },
+    IndexAssignment {
+        target: Box<ASTNode>,
+        index: Box<ASTNode>,
+        value: Box<ASTNode>,
+    },
+    SliceExpression {
+        target: Box<ASTNode>,
+        start: Option<Box<ASTNode>>,
+        end: Option<Box<ASTNode>>,
+    },
+    MethodCall {
+        receiver: Box<ASTNode>,
+        method: Rc<str>,
+        arguments: Vec<ASTNode>,
+    },
+    /// `(a, b, c)`: evaluates each expression in order, yielding the last.
+    SequenceExpression(Vec<ASTNode>),
+    /// A call of a function value computed by an expression, such as
+    /// `handlers["click"]()`. Calls by plain name are `FunctionCall`s.
+    CallExpression {
+        callee: Box<ASTNode>,
+        arguments: Vec<ASTNode>,
+    },
+    // Runtime values: arrays and maps are shared, so copies of the value
+    // alias the same underlying storage.
+    Array(Rc<RefCell<Vec<ASTNode>>>),
+    Map(Rc<RefCell<HashMap<String, ASTNode>>>),
+    /// A function wrapped by `memoize`, with the results so far keyed by the
+    /// printed form of the arguments. Copies of the wrapper share the cache.
+    Memoized {
+        function: Box<ASTNode>,
+        cache: Rc<RefCell<HashMap<String, ASTNode>>>,
+    },
+}
+
+/// How deeply expressions and blocks may nest. Parsing, and later running,
+/// the tree recurses once per level, so unbounded nesting would overflow the
+/// stack on adversarial input.
+const MAX_NESTING: usize = 200;
+
+thread_local! {
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts levels of nesting for as long as it is held.
+struct NestingGuard(usize);
+
+impl NestingGuard {
+    /// Counts one level, for a construct that recurses into itself.
+    fn enter(what: &str) -> Result<Self, String> {
+        let mut guard = NestingGuard::none();
+        guard.deepen(what)?;
+        Ok(guard)
+    }
+
+    /// Counts nothing yet, for a loop that builds a tree one level at a time.
+    fn none() -> Self {
+        NestingGuard(0)
+    }
+
+    /// Counts one more level. Operator chains are parsed in a loop, but
+    /// `a + b + c` still nests like `(a + b) + c` and is run recursively.
+    fn deepen(&mut self, what: &str) -> Result<(), String> {
+        NESTING.with(|depth| {
+            if depth.get() >= MAX_NESTING {
+                return Err(format!("{} too deeply nested.", what));
+            }
+            depth.set(depth.get() + 1);
+            self.0 += 1;
+            Ok(())
+        })
+    }
+}
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        NESTING.with(|depth| depth.set(depth.get() - self.0));
+    }
+}
+
+thread_local! {
+    /// Labels of the loops around the point being parsed, innermost last;
+    /// unlabeled loops are `None`.
+    static LOOPS: RefCell<Vec<Option<Rc<str>>>> = const { RefCell::new(Vec::new()) };
+}
+
+thread_local! {
+    /// Whether the point being parsed is inside a function body.
+    static IN_FUNCTION: Cell<bool> = const { Cell::new(false) };
+    /// Whether the point being parsed is inside a `defer` block of the
+    /// innermost function, or of the top level.
+    static IN_DEFER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the parser as inside a function body for as long as it is held.
+/// A function declared in a `defer` block has a body of its own to leave.
+struct FunctionGuard(bool, bool);
+
+impl FunctionGuard {
+    fn enter() -> Self {
+        FunctionGuard(IN_FUNCTION.replace(true), IN_DEFER.replace(false))
+    }
+}
+
+impl Drop for FunctionGuard {
+    fn drop(&mut self) {
+        IN_FUNCTION.set(self.0);
+        IN_DEFER.set(self.1);
+    }
+}
+
+/// Marks the parser as inside a `defer` block for as long as it is held.
+struct DeferGuard(bool);
+
+impl DeferGuard {
+    fn enter() -> Self {
+        DeferGuard(IN_DEFER.replace(true))
+    }
+}
+
+impl Drop for DeferGuard {
+    fn drop(&mut self) {
+        IN_DEFER.set(self.0);
+    }
+}
+
+/// Sets the loops that `break` and `continue` can reach for as long as it is
+/// held, restoring the previous ones when dropped.
+struct LoopGuard(Vec<Option<Rc<str>>>);
+
+impl LoopGuard {
+    /// Enters the body of a loop with the given label.
+    fn enter(label: Option<Rc<str>>) -> Self {
+        LOOPS.with(|loops| {
+            let saved = loops.borrow().clone();
+            loops.borrow_mut().push(label);
+            LoopGuard(saved)
+        })
+    }
+
+    /// Enters a body that `break` and `continue` cannot leave: a function,
+    /// which may be called from anywhere, or a `defer` block.
+    fn isolate() -> Self {
+        LoopGuard(LOOPS.with(RefCell::take))
+    }
+}
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        LOOPS.with(|loops| *loops.borrow_mut() = std::mem::take(&mut self.0));
+    }
+}
+
+/// A syntax error and the position of the token where it was detected.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl ParseError {
+    fn new(message: String, position: Position) -> Self {
+        ParseError {
+            message,
+            line: position.line,
+            col: position.col,
+        }
+    }
+
+    /// Places `message` at the next token of `tokens`, where parsing stopped.
+    fn at(message: String, tokens: &Peekable<Iter<Token>>, positions: &[Position]) -> Self {
+        let index = (positions.len() - tokens.len()).min(positions.len() - 1);
+        ParseError::new(message, positions[index])
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}, column {}: {}", self.line, self.col, self.message)
+    }
 }
 
-pub fn parse(tokens: &[Token]) -> Result<Vec<ASTNode>, String> {
-    let mut tokens = tokens.iter().peekable();
+/// Parses a whole program. A source with no statements (empty, or only
+/// whitespace and comments) lexes to just `EOF` and yields an empty program.
+/// `positions` holds the start of each token, as returned by `lex`.
+pub fn parse(source: &[Token], positions: &[Position]) -> Result<Vec<ASTNode>, ParseError> {
+    let mut tokens = source.iter().peekable();
     let mut ast = Vec::new();
 
     while let Some(token) = tokens.peek() {
         if **token == Token::EOF {
             break;
         }
-        let node = parse_statement(&mut tokens)?;
+        let node = parse_statement(&mut tokens)
+            .map_err(|message| ParseError::at(message, &tokens, positions))?;
         ast.push(node);
     }
     Ok(ast)
 }
 
+/// Parses the source of an `eval`: statements as in `parse`, optionally
+/// followed by a final expression of any kind, whose semicolon is optional.
+pub fn parse_eval(source: &[Token], positions: &[Position]) -> Result<Vec<ASTNode>, ParseError> {
+    let mut tokens = source.iter().peekable();
+    let mut ast = Vec::new();
+
+    while let Some(token) = tokens.peek() {
+        if **token == Token::EOF {
+            break;
+        }
+        let mut statement_tokens = tokens.clone();
+        match parse_statement(&mut statement_tokens) {
+            Ok(node) => {
+                tokens = statement_tokens;
+                ast.push(node);
+            }
+            Err(message) => {
+                // Unless the rest is one expression, report whichever of the
+                // statement and expression errors got further into the source
+                let error = ParseError::at(message, &statement_tokens, positions);
+                let expr = match parse_expression(&mut tokens) {
+                    Ok(expr) => expr,
+                    Err(message) => {
+                        let expr_error = ParseError::at(message, &tokens, positions);
+                        if (expr_error.line, expr_error.col) > (error.line, error.col) {
+                            return Err(expr_error);
+                        }
+                        return Err(error);
+                    }
+                };
+                if let Some(Token::Semicolon) = tokens.peek() {
+                    tokens.next(); // Consume ';'
+                }
+                if !matches!(tokens.peek(), None | Some(Token::EOF)) {
+                    return Err(error);
+                }
+                ast.push(expr);
+                break;
+            }
+        }
+    }
+    Ok(ast)
+}
+
+/// Counts every node in the tree, including the statements themselves.
+pub fn count_nodes(nodes: &[ASTNode]) -> usize {
+    nodes.iter().map(count_node).sum()
+}
+
+fn count_node(node: &ASTNode) -> usize {
+    let children = match node {
+        ASTNode::Program(statements) => count_nodes(statements),
+        ASTNode::PrintStatement(expr)
+        | ASTNode::VariableAssignment(_, expr)
+        | ASTNode::LetDeclaration(_, expr)
+        | ASTNode::ThrowStatement(expr)
+        | ASTNode::ReturnStatement(Some(expr)) => count_node(expr),
+        ASTNode::IfStatement {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            count_node(condition)
+                + count_nodes(then_branch)
+                + else_branch.as_deref().map_or(0, count_nodes)
+        }
+        ASTNode::GuardStatement { condition, else_block } => {
+            count_node(condition) + count_nodes(else_block)
+        }
+        ASTNode::DeferStatement(body) => count_nodes(body),
+        ASTNode::WhileStatement { condition, body, .. }
+        | ASTNode::RepeatStatement { count: condition, body, .. } => {
+            count_node(condition) + count_nodes(body)
+        }
+        ASTNode::ForeachStatement { iterable, body, .. } => {
+            count_node(iterable) + count_nodes(body)
+        }
+        ASTNode::FunctionDeclaration { body, .. } => count_nodes(body),
+        ASTNode::FunctionCall { arguments, .. } | ASTNode::ArrayLiteral(arguments) => {
+            count_nodes(arguments)
+        }
+        ASTNode::TryCatch {
+            try_block,
+            catch_block,
+            ..
+        } => count_nodes(try_block) + count_nodes(catch_block),
+        ASTNode::BinaryExpression { left, right, .. } => count_node(left) + count_node(right),
+        ASTNode::UnaryExpression { operand, .. } => count_node(operand),
+        ASTNode::MapLiteral(entries) => entries
+            .iter()
+            .map(|(key, value)| count_node(key) + count_node(value))
+            .sum(),
+        ASTNode::IndexExpression { target, index }
+        | ASTNode::OptionalIndexExpression { target, index } => {
+            count_node(target) + count_node(index)
+        }
+        ASTNode::IndexAssignment { target, index, value } => {
+            count_node(target) + count_node(index) + count_node(value)
+        }
+        ASTNode::ChainedAssignment { targets, value } => count_nodes(targets) + count_node(value),
+        ASTNode::ParallelAssignment { targets, values } => count_nodes(targets) + count_nodes(values),
+        ASTNode::SliceExpression { target, start, end } => {
+            count_node(target)
+                + start.as_deref().map_or(0, count_node)
+                + end.as_deref().map_or(0, count_node)
+        }
+        ASTNode::MethodCall { receiver, arguments, .. } => {
+            count_node(receiver) + count_nodes(arguments)
+        }
+        ASTNode::CallExpression { callee, arguments } => {
+            count_node(callee) + count_nodes(arguments)
+        }
+        ASTNode::SequenceExpression(expressions) => count_nodes(expressions),
+        _ => 0,
+    };
+    1 + children
+}
+
 fn parse_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
     match tokens.peek() {
         Some(Token::Print) => parse_print_statement(tokens),
         Some(Token::If) => parse_if_statement(tokens),
+        Some(Token::While) => parse_while_statement(tokens, None),
+        Some(Token::Repeat) => parse_repeat_statement(tokens, None),
+        Some(Token::Foreach) => parse_foreach_statement(tokens, None),
+        Some(Token::Break | Token::Continue) => parse_loop_jump(tokens),
+        Some(Token::Stop) => parse_stop_statement(tokens),
+        Some(Token::Guard) => parse_guard_statement(tokens),
+        Some(Token::Defer) => parse_defer_statement(tokens),
         Some(Token::Function) => parse_function_declaration(tokens),
-        Some(Token::Identifier(_)) => parse_assignment_or_expression_statement(tokens),
+        Some(Token::Return) => parse_return_statement(tokens),
+        Some(Token::Try) => parse_try_statement(tokens),
+        Some(Token::Throw) => parse_throw_statement(tokens),
+        Some(Token::Let) => parse_let_declaration(tokens),
+        Some(Token::Global) => parse_global_declaration(tokens),
+        Some(Token::Import) => parse_import_statement(tokens),
+        Some(Token::Identifier(_)) => {
+            // `name:` can only begin a labeled loop
+            if tokens.clone().nth(1) == Some(&Token::Colon) {
+                parse_labeled_loop(tokens)
+            } else {
+                parse_assignment_or_expression_statement(tokens)
+            }
+        }
         Some(Token::Semicolon) => {
             tokens.next(); // Consume ';'
             Ok(ASTNode::Program(Vec::new())) // Empty statement
@@ -71,17 +486,34 @@ fn parse_print_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode,
     Ok(ASTNode::PrintStatement(Box::new(expr)))
 }
 
+fn parse_return_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    if !IN_FUNCTION.get() {
+        return Err("'return' outside of a function.".into());
+    }
+    if IN_DEFER.get() {
+        return Err("'return' inside a 'defer' block.".into());
+    }
+    tokens.next(); // Consume 'return'
+    if let Some(Token::Semicolon) = tokens.peek() {
+        tokens.next(); // Consume ';'
+        return Ok(ASTNode::ReturnStatement(None));
+    }
+    let expr = parse_expression(tokens)?;
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::ReturnStatement(Some(Box::new(expr))))
+}
+
 fn parse_if_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
     tokens.next(); // Consume 'if'
     expect_token(tokens, Token::LeftParen)?;
     let condition = parse_expression(tokens)?;
     expect_token(tokens, Token::RightParen)?;
-    expect_token(tokens, Token::LeftBrace)?;
-    let then_branch = parse_block(tokens)?;
+    let then_branch = parse_branch(tokens)?;
+    // A braceless inner `if` has already claimed any `else` that follows
+    // it, so an `else` binds to the nearest `if`.
     let else_branch = if let Some(Token::Else) = tokens.peek() {
         tokens.next(); // Consume 'else'
-        expect_token(tokens, Token::LeftBrace)?;
-        Some(parse_block(tokens)?)
+        Some(parse_branch(tokens)?)
     } else {
         None
     };
@@ -92,26 +524,257 @@ fn parse_if_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, Str
     })
 }
 
-fn parse_function_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
-    tokens.next(); // Consume 'function'
-    let name = if let Some(Token::Identifier(name)) = tokens.next() {
-        name.clone()
+/// Parses a branch of an `if`: a braced block, or a single statement.
+fn parse_branch(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<ASTNode>, String> {
+    match tokens.peek() {
+        Some(Token::LeftBrace) => {
+            tokens.next(); // Consume '{'
+            parse_block(tokens)
+        }
+        Some(Token::EOF) | None => Err("Expected a statement or block.".into()),
+        Some(_) => {
+            let _nesting = NestingGuard::enter("Block")?;
+            Ok(vec![parse_statement(tokens)?])
+        }
+    }
+}
+
+fn parse_guard_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'guard'
+    let condition = parse_expression(tokens)?;
+    expect_token(tokens, Token::Else)?;
+    expect_token(tokens, Token::LeftBrace)?;
+    let else_block = parse_block(tokens)?;
+    // Falling out of the else block would run the code the guard protects.
+    // A `break` or `continue` here was already checked against the
+    // enclosing loops while the block was parsed, and `stop` likewise.
+    if !matches!(
+        else_block.last(),
+        Some(
+            ASTNode::ReturnStatement(_)
+                | ASTNode::ThrowStatement(_)
+                | ASTNode::BreakStatement(_)
+                | ASTNode::ContinueStatement(_)
+                | ASTNode::StopStatement
+        )
+    ) {
+        return Err("The else block of 'guard' must end with 'return', 'throw', 'break', \
+                    'continue' or 'stop'."
+            .into());
+    }
+    Ok(ASTNode::GuardStatement {
+        condition: Box::new(condition),
+        else_block,
+    })
+}
+
+fn parse_defer_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'defer'
+    expect_token(tokens, Token::LeftBrace)?;
+    // A deferred block runs once its scope is already finishing, so it may
+    // not try to leave it again
+    let _loops = LoopGuard::isolate();
+    let _defer = DeferGuard::enter();
+    Ok(ASTNode::DeferStatement(parse_block(tokens)?))
+}
+
+fn parse_labeled_loop(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let label = expect_identifier(tokens, "Expected a loop label.")?;
+    tokens.next(); // Consume ':'
+    match tokens.peek() {
+        Some(Token::While) => parse_while_statement(tokens, Some(label)),
+        Some(Token::Repeat) => parse_repeat_statement(tokens, Some(label)),
+        Some(Token::Foreach) => parse_foreach_statement(tokens, Some(label)),
+        _ => Err(format!("Expected a loop after the label '{}'.", label)),
+    }
+}
+
+fn parse_loop_jump(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let keyword = if tokens.peek() == Some(&&Token::Break) { "break" } else { "continue" };
+    if LOOPS.with(|loops| loops.borrow().is_empty()) {
+        return Err(format!("'{}' outside of a loop.", keyword));
+    }
+    let is_break = tokens.next() == Some(&Token::Break);
+    let label = match tokens.peek() {
+        Some(Token::Identifier(label)) => {
+            if !LOOPS.with(|loops| loops.borrow().contains(&Some(label.clone()))) {
+                return Err(format!("Unknown loop label '{}'.", label));
+            }
+            let label = label.clone();
+            tokens.next(); // Consume the label
+            Some(label)
+        }
+        _ => None,
+    };
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(if is_break {
+        ASTNode::BreakStatement(label)
     } else {
-        return Err("Expected function name.".into());
+        ASTNode::ContinueStatement(label)
+    })
+}
+
+fn parse_stop_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    if IN_FUNCTION.get() {
+        return Err("'stop' inside a function; use 'return' to leave it.".into());
+    }
+    if IN_DEFER.get() {
+        return Err("'stop' inside a 'defer' block.".into());
+    }
+    tokens.next(); // Consume 'stop'
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::StopStatement)
+}
+
+fn parse_while_statement(
+    tokens: &mut Peekable<Iter<Token>>,
+    label: Option<Rc<str>>,
+) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'while'
+    expect_token(tokens, Token::LeftParen)?;
+    let condition = parse_expression(tokens)?;
+    expect_token(tokens, Token::RightParen)?;
+    expect_token(tokens, Token::LeftBrace)?;
+    let body = {
+        let _loops = LoopGuard::enter(label.clone());
+        parse_block(tokens)?
+    };
+    Ok(ASTNode::WhileStatement {
+        label,
+        condition: Box::new(condition),
+        body,
+    })
+}
+
+fn parse_repeat_statement(
+    tokens: &mut Peekable<Iter<Token>>,
+    label: Option<Rc<str>>,
+) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'repeat'
+    expect_token(tokens, Token::LeftParen)?;
+    let count = parse_expression(tokens)?;
+    expect_token(tokens, Token::RightParen)?;
+    expect_token(tokens, Token::LeftBrace)?;
+    let body = {
+        let _loops = LoopGuard::enter(label.clone());
+        parse_block(tokens)?
     };
+    Ok(ASTNode::RepeatStatement {
+        label,
+        count: Box::new(count),
+        body,
+    })
+}
+
+fn parse_foreach_statement(
+    tokens: &mut Peekable<Iter<Token>>,
+    label: Option<Rc<str>>,
+) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'foreach'
+    expect_token(tokens, Token::LeftParen)?;
+    let mut variable = expect_identifier(tokens, "Expected a loop variable after 'foreach ('.")?;
+    let mut index = None;
+    if let Some(Token::Comma) = tokens.peek() {
+        tokens.next(); // Consume ','
+        index = Some(variable);
+        variable = expect_identifier(tokens, "Expected a loop variable after ','.")?;
+    }
+    expect_token(tokens, Token::In)?;
+    let iterable = parse_expression(tokens)?;
+    expect_token(tokens, Token::RightParen)?;
+    expect_token(tokens, Token::LeftBrace)?;
+    let body = {
+        let _loops = LoopGuard::enter(label.clone());
+        parse_block(tokens)?
+    };
+    Ok(ASTNode::ForeachStatement {
+        label,
+        index,
+        variable,
+        iterable: Box::new(iterable),
+        body,
+    })
+}
+
+fn parse_let_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'let'
+    let name = expect_identifier(tokens, "Expected variable name after 'let'.")?;
+    expect_token(tokens, Token::Assign)?;
+    let value = parse_expression(tokens)?;
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::LetDeclaration(name, Box::new(value)))
+}
+
+fn parse_global_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'global'
+    let name = expect_identifier(tokens, "Expected variable name after 'global'.")?;
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::GlobalDeclaration(name))
+}
+
+fn parse_import_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'import'
+    let path = match tokens.next_if(|token| matches!(token, Token::StringLiteral(_))) {
+        Some(Token::StringLiteral(path)) => path.clone(),
+        _ => return Err("Expected a file path string after 'import'.".into()),
+    };
+    // `as` is only special here, so it stays usable as a variable name
+    let alias = match tokens.peek() {
+        Some(Token::Identifier(word)) if &**word == "as" => {
+            tokens.next(); // Consume 'as'
+            Some(expect_identifier(tokens, "Expected a namespace name after 'as'.")?)
+        }
+        _ => None,
+    };
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::ImportStatement { path, alias })
+}
+
+fn parse_throw_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'throw'
+    let expr = parse_expression(tokens)?;
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::ThrowStatement(Box::new(expr)))
+}
+
+fn parse_try_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'try'
+    expect_token(tokens, Token::LeftBrace)?;
+    let try_block = parse_block(tokens)?;
+    expect_token(tokens, Token::Catch)?;
+    expect_token(tokens, Token::LeftParen)?;
+    let error_name = expect_identifier(tokens, "Expected error variable name in catch.")?;
+    expect_token(tokens, Token::RightParen)?;
+    expect_token(tokens, Token::LeftBrace)?;
+    let catch_block = parse_block(tokens)?;
+    Ok(ASTNode::TryCatch {
+        try_block,
+        error_name,
+        catch_block,
+    })
+}
+
+fn parse_function_declaration(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    tokens.next(); // Consume 'function'
+    let name = expect_identifier(tokens, "Expected function name.")?;
     expect_token(tokens, Token::LeftParen)?;
     let parameters = parse_parameters(tokens)?;
     expect_token(tokens, Token::RightParen)?;
     expect_token(tokens, Token::LeftBrace)?;
-    let body = parse_block(tokens)?;
+    let body = {
+        let _loops = LoopGuard::isolate();
+        let _function = FunctionGuard::enter();
+        parse_block(tokens)?
+    };
     Ok(ASTNode::FunctionDeclaration {
         name,
         parameters,
-        body,
+        body: Rc::new(body),
     })
 }
 
-fn parse_parameters(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<String>, String> {
+/// Parses parameter names up to the closing `)`; a trailing comma is allowed.
+fn parse_parameters(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<Rc<str>>, String> {
     let mut params = Vec::new();
     while let Some(token) = tokens.peek() {
         match token {
@@ -132,45 +795,182 @@ fn parse_parameters(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<String>, S
     Ok(params)
 }
 
+/// Parses statements up to and including the closing `}` of a block whose
+/// `{` has already been consumed. An empty block yields no statements.
 fn parse_block(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<ASTNode>, String> {
+    let _nesting = NestingGuard::enter("Block")?;
     let mut statements = Vec::new();
-    while let Some(token) = tokens.peek() {
-        if **token == Token::RightBrace {
-            tokens.next(); // Consume '}'
-            break;
+    loop {
+        match tokens.peek() {
+            Some(Token::RightBrace) => {
+                tokens.next(); // Consume '}'
+                return Ok(statements);
+            }
+            // `parse_statement` accepts EOF without consuming it, so stop
+            // here rather than loop forever on an unclosed block
+            Some(Token::EOF) | None => return Err("Expected '}' to close the block.".into()),
+            Some(_) => statements.push(parse_statement(tokens)?),
         }
-        let stmt = parse_statement(tokens)?;
-        statements.push(stmt);
     }
-    Ok(statements)
 }
 
 fn parse_assignment_or_expression_statement(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
     let expr = parse_expression(tokens)?;
-    if let Some(Token::Assign) = tokens.peek() {
-        if let ASTNode::Identifier(name) = expr {
+    match tokens.peek() {
+        Some(Token::Comma) => parse_parallel_assignment(tokens, expr),
+        Some(Token::Assign) => {
+            check_assignment_targets(std::slice::from_ref(&expr))?;
             tokens.next(); // Consume '='
-            let value = parse_expression(tokens)?;
+            let mut targets = vec![expr];
+            let mut value = parse_expression(tokens)?;
+            while let Some(Token::Assign) = tokens.peek() {
+                check_assignment_targets(std::slice::from_ref(&value))?;
+                tokens.next(); // Consume '='
+                targets.push(value);
+                value = parse_expression(tokens)?;
+            }
             expect_token(tokens, Token::Semicolon)?;
-            Ok(ASTNode::VariableAssignment(name, Box::new(value)))
-        } else {
-            Err("Invalid assignment target.".into())
+            if targets.len() > 1 {
+                return Ok(ASTNode::ChainedAssignment {
+                    targets,
+                    value: Box::new(value),
+                });
+            }
+            match targets.pop() {
+                Some(ASTNode::Identifier(name)) => {
+                    Ok(ASTNode::VariableAssignment(name, Box::new(value)))
+                }
+                Some(ASTNode::IndexExpression { target, index }) => Ok(ASTNode::IndexAssignment {
+                    target,
+                    index,
+                    value: Box::new(value),
+                }),
+                _ => Err("Invalid assignment target.".into()),
+            }
+        }
+        _ => {
+            expect_token(tokens, Token::Semicolon)?;
+            Ok(expr)
         }
-    } else {
-        expect_token(tokens, Token::Semicolon)?;
-        Ok(expr)
     }
 }
 
+/// Parses the rest of `a, b = x, y;` after the first target.
+fn parse_parallel_assignment(tokens: &mut Peekable<Iter<Token>>, first: ASTNode) -> Result<ASTNode, String> {
+    let mut targets = vec![first];
+    while let Some(Token::Comma) = tokens.peek() {
+        tokens.next(); // Consume ','
+        targets.push(parse_expression(tokens)?);
+    }
+    check_assignment_targets(&targets)?;
+    expect_token(tokens, Token::Assign)?;
+    let mut values = vec![parse_expression(tokens)?];
+    while let Some(Token::Comma) = tokens.peek() {
+        tokens.next(); // Consume ','
+        values.push(parse_expression(tokens)?);
+    }
+    if targets.len() != values.len() {
+        return Err(format!(
+            "Cannot assign {} values to {} targets.",
+            values.len(),
+            targets.len()
+        ));
+    }
+    expect_token(tokens, Token::Semicolon)?;
+    Ok(ASTNode::ParallelAssignment { targets, values })
+}
+
+/// Only variables and indexed elements can be assigned to.
+fn check_assignment_targets(targets: &[ASTNode]) -> Result<(), String> {
+    for target in targets {
+        if !matches!(target, ASTNode::Identifier(_) | ASTNode::IndexExpression { .. }) {
+            return Err("Invalid assignment target.".into());
+        }
+    }
+    Ok(())
+}
+
 fn parse_expression(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
-    parse_equality(tokens)
+    let _nesting = NestingGuard::enter("Expression")?;
+    parse_pipe(tokens)
+}
+
+/// `x |> f` becomes `f(x)` and `x |> f(a)` becomes `f(x, a)`; the same goes
+/// for method calls and calls of computed functions such as `fs[0](a)`. Any
+/// other callable, such as `fs[0]` or `(f ?? g)`, is called with `x` alone.
+fn parse_pipe(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
+    let mut expr = parse_coalesce(tokens)?;
+    while let Some(Token::Pipe) = tokens.peek() {
+        nesting.deepen("Expression")?;
+        tokens.next(); // Consume '|>'
+        expr = match parse_postfix(tokens)? {
+            ASTNode::Identifier(name) => ASTNode::FunctionCall {
+                name,
+                arguments: vec![expr],
+            },
+            ASTNode::FunctionCall { name, mut arguments } => {
+                arguments.insert(0, expr);
+                ASTNode::FunctionCall { name, arguments }
+            }
+            ASTNode::CallExpression { callee, mut arguments } => {
+                arguments.insert(0, expr);
+                ASTNode::CallExpression { callee, arguments }
+            }
+            ASTNode::MethodCall { receiver, method, mut arguments } => {
+                arguments.insert(0, expr);
+                ASTNode::MethodCall { receiver, method, arguments }
+            }
+            callee => ASTNode::CallExpression {
+                callee: Box::new(callee),
+                arguments: vec![expr],
+            },
+        };
+    }
+    Ok(expr)
+}
+
+fn parse_coalesce(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
+    let mut expr = parse_xor(tokens)?;
+    while let Some(Token::QuestionQuestion) = tokens.peek() {
+        nesting.deepen("Expression")?;
+        let operator = tokens.next().unwrap().clone();
+        let right = parse_xor(tokens)?;
+        expr = ASTNode::BinaryExpression {
+            left: Box::new(expr),
+            operator,
+            right: Box::new(right),
+        };
+    }
+    Ok(expr)
+}
+
+/// `a ^^ b` is true when exactly one side is truthy. Both sides are always
+/// evaluated, since neither alone can decide the result.
+fn parse_xor(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
+    let mut expr = parse_equality(tokens)?;
+    while let Some(Token::CaretCaret) = tokens.peek() {
+        nesting.deepen("Expression")?;
+        let operator = tokens.next().unwrap().clone();
+        let right = parse_equality(tokens)?;
+        expr = ASTNode::BinaryExpression {
+            left: Box::new(expr),
+            operator,
+            right: Box::new(right),
+        };
+    }
+    Ok(expr)
 }
 
 fn parse_equality(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
     let mut expr = parse_comparison(tokens)?;
     while let Some(token) = tokens.peek() {
         match token {
             Token::EqualEqual | Token::NotEqual => {
+                nesting.deepen("Expression")?;
                 let operator = tokens.next().unwrap().clone();
                 let right = parse_comparison(tokens)?;
                 expr = ASTNode::BinaryExpression {
@@ -186,10 +986,16 @@ fn parse_equality(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String>
 }
 
 fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
     let mut expr = parse_addition(tokens)?;
     while let Some(token) = tokens.peek() {
         match token {
-            Token::LessThan | Token::GreaterThan | Token::LessEqual | Token::GreaterEqual => {
+            Token::LessThan
+            | Token::GreaterThan
+            | Token::LessEqual
+            | Token::GreaterEqual
+            | Token::In => {
+                nesting.deepen("Expression")?;
                 let operator = tokens.next().unwrap().clone();
                 let right = parse_addition(tokens)?;
                 expr = ASTNode::BinaryExpression {
@@ -205,10 +1011,12 @@ fn parse_comparison(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, Strin
 }
 
 fn parse_addition(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
     let mut expr = parse_multiplication(tokens)?;
     while let Some(token) = tokens.peek() {
         match token {
             Token::Plus | Token::Minus => {
+                nesting.deepen("Expression")?;
                 let operator = tokens.next().unwrap().clone();
                 let right = parse_multiplication(tokens)?;
                 expr = ASTNode::BinaryExpression {
@@ -224,10 +1032,12 @@ fn parse_addition(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String>
 }
 
 fn parse_multiplication(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
     let mut expr = parse_unary(tokens)?;
     while let Some(token) = tokens.peek() {
         match token {
             Token::Star | Token::Slash => {
+                nesting.deepen("Expression")?;
                 let operator = tokens.next().unwrap().clone();
                 let right = parse_unary(tokens)?;
                 expr = ASTNode::BinaryExpression {
@@ -243,26 +1053,78 @@ fn parse_multiplication(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, S
 }
 
 fn parse_unary(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
-    if let Some(token) = tokens.peek() {
-        match token {
-            Token::Minus | Token::Bang => {
-                let operator = tokens.next().unwrap().clone();
-                let operand = parse_unary(tokens)?;
-                return Ok(ASTNode::UnaryExpression {
-                    operator,
-                    operand: Box::new(operand),
-                });
+    if let Some(Token::Minus | Token::Bang) = tokens.peek() {
+        let operator = tokens.next().unwrap().clone();
+        let _nesting = NestingGuard::enter("Expression")?;
+        let operand = parse_unary(tokens)?;
+        return Ok(ASTNode::UnaryExpression {
+            operator,
+            operand: Box::new(operand),
+        });
+    }
+    parse_postfix(tokens)
+}
+
+/// Applies index, optional index and method call suffixes left to right,
+/// so `rows()[0].upper()` and `grid[1][2]` parse as one expression.
+fn parse_postfix(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
+    let mut nesting = NestingGuard::none();
+    let mut expr = parse_primary(tokens)?;
+    loop {
+        if let Some(Token::LeftBracket | Token::Question | Token::Dot | Token::LeftParen) =
+            tokens.peek()
+        {
+            nesting.deepen("Expression")?;
+        }
+        match tokens.peek() {
+            Some(Token::LeftBracket) => expr = parse_index(tokens, expr)?,
+            Some(Token::Question) => {
+                tokens.next(); // Consume '?'
+                expect_token(tokens, Token::LeftBracket)?;
+                let index = parse_expression(tokens)?;
+                expect_token(tokens, Token::RightBracket)?;
+                expr = ASTNode::OptionalIndexExpression {
+                    target: Box::new(expr),
+                    index: Box::new(index),
+                };
+            }
+            Some(Token::Dot) => {
+                tokens.next(); // Consume '.'
+                let method = expect_identifier(tokens, "Expected method name after '.'.")?;
+                expect_token(tokens, Token::LeftParen)?;
+                let arguments = parse_arguments(tokens)?;
+                expect_token(tokens, Token::RightParen)?;
+                expr = ASTNode::MethodCall {
+                    receiver: Box::new(expr),
+                    method,
+                    arguments,
+                };
             }
-            _ => {}
+            Some(Token::LeftParen) => {
+                tokens.next(); // Consume '('
+                let arguments = parse_arguments(tokens)?;
+                expect_token(tokens, Token::RightParen)?;
+                expr = ASTNode::CallExpression {
+                    callee: Box::new(expr),
+                    arguments,
+                };
+            }
+            _ => return Ok(expr),
         }
     }
-    parse_primary(tokens)
 }
 
 fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String> {
-    match tokens.next() {
+    match tokens.next_if(|token| starts_primary(token)) {
         Some(Token::NumberLiteral(n)) => Ok(ASTNode::NumberLiteral(*n)),
         Some(Token::StringLiteral(s)) => Ok(ASTNode::StringLiteral(s.clone())),
+        Some(Token::PrefixedString(tag, value)) => Ok(ASTNode::PrefixedString {
+            tag: tag.clone(),
+            value: value.clone(),
+        }),
+        Some(Token::True) => Ok(ASTNode::BooleanLiteral(true)),
+        Some(Token::False) => Ok(ASTNode::BooleanLiteral(false)),
+        Some(Token::Nil) => Ok(ASTNode::NilLiteral),
         Some(Token::Identifier(name)) => {
             if let Some(Token::LeftParen) = tokens.peek() {
                 tokens.next(); // Consume '('
@@ -278,13 +1140,103 @@ fn parse_primary(tokens: &mut Peekable<Iter<Token>>) -> Result<ASTNode, String>
         }
         Some(Token::LeftParen) => {
             let expr = parse_expression(tokens)?;
+            // Commas only sequence expressions inside parentheses, so they
+            // never clash with argument lists or array literals
+            let mut expressions = vec![expr];
+            while let Some(Token::Comma) = tokens.peek() {
+                tokens.next(); // Consume ','
+                expressions.push(parse_expression(tokens)?);
+            }
             expect_token(tokens, Token::RightParen)?;
-            Ok(expr)
+            if expressions.len() == 1 {
+                return Ok(expressions.remove(0));
+            }
+            Ok(ASTNode::SequenceExpression(expressions))
+        }
+        Some(Token::LeftBracket) => {
+            let elements = parse_array_elements(tokens)?;
+            expect_token(tokens, Token::RightBracket)?;
+            Ok(ASTNode::ArrayLiteral(elements))
+        }
+        Some(Token::LeftBrace) => {
+            let entries = parse_map_entries(tokens)?;
+            expect_token(tokens, Token::RightBrace)?;
+            Ok(ASTNode::MapLiteral(entries))
         }
         _ => Err("Expected an expression.".into()),
     }
 }
 
+/// Parses `[index]` or a slice `[start:end]` (either bound may be omitted)
+/// applied to `target`.
+fn parse_index(tokens: &mut Peekable<Iter<Token>>, target: ASTNode) -> Result<ASTNode, String> {
+    tokens.next(); // Consume '['
+    let start = if let Some(Token::Colon) = tokens.peek() {
+        None
+    } else {
+        Some(Box::new(parse_expression(tokens)?))
+    };
+    if let Some(Token::Colon) = tokens.peek() {
+        tokens.next(); // Consume ':'
+        let end = if let Some(Token::RightBracket) = tokens.peek() {
+            None
+        } else {
+            Some(Box::new(parse_expression(tokens)?))
+        };
+        expect_token(tokens, Token::RightBracket)?;
+        return Ok(ASTNode::SliceExpression {
+            target: Box::new(target),
+            start,
+            end,
+        });
+    }
+    expect_token(tokens, Token::RightBracket)?;
+    Ok(ASTNode::IndexExpression {
+        target: Box::new(target),
+        index: start.unwrap(),
+    })
+}
+
+/// Parses array elements up to the closing `]`; a trailing comma is allowed.
+fn parse_array_elements(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<ASTNode>, String> {
+    let mut elements = Vec::new();
+    while let Some(token) = tokens.peek() {
+        if **token == Token::RightBracket {
+            break;
+        }
+        let element = parse_expression(tokens)?;
+        elements.push(element);
+        if let Some(Token::Comma) = tokens.peek() {
+            tokens.next(); // Consume ','
+        } else {
+            break;
+        }
+    }
+    Ok(elements)
+}
+
+/// Parses `key: value` entries up to the closing `}`; a trailing comma is
+/// allowed.
+fn parse_map_entries(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<(ASTNode, ASTNode)>, String> {
+    let mut entries = Vec::new();
+    while let Some(token) = tokens.peek() {
+        if **token == Token::RightBrace {
+            break;
+        }
+        let key = parse_expression(tokens)?;
+        expect_token(tokens, Token::Colon)?;
+        let value = parse_expression(tokens)?;
+        entries.push((key, value));
+        if let Some(Token::Comma) = tokens.peek() {
+            tokens.next(); // Consume ','
+        } else {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses call arguments up to the closing `)`; a trailing comma is allowed.
 fn parse_arguments(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<ASTNode>, String> {
     let mut args = Vec::new();
     while let Some(token) = tokens.peek() {
@@ -302,14 +1254,38 @@ fn parse_arguments(tokens: &mut Peekable<Iter<Token>>) -> Result<Vec<ASTNode>, S
     Ok(args)
 }
 
+fn starts_primary(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::NumberLiteral(_)
+            | Token::StringLiteral(_)
+            | Token::PrefixedString(..)
+            | Token::True
+            | Token::False
+            | Token::Nil
+            | Token::Identifier(_)
+            | Token::LeftParen
+            | Token::LeftBracket
+            | Token::LeftBrace
+    )
+}
+
+// The expect helpers only consume a token that matches, so on error the
+// offending token is still next in line and its position is reported.
 fn expect_token(tokens: &mut Peekable<Iter<Token>>, expected: Token) -> Result<(), String> {
-    if let Some(token) = tokens.next() {
-        if *token == expected {
+    match tokens.peek() {
+        Some(token) if **token == expected => {
+            tokens.next();
             Ok(())
-        } else {
-            Err(format!("Expected token {:?}, found {:?}", expected, token))
         }
-    } else {
-        Err(format!("Expected token {:?}, found end of input", expected))
+        Some(token) => Err(format!("Expected token {:?}, found {:?}", expected, token)),
+        None => Err(format!("Expected token {:?}, found end of input", expected)),
+    }
+}
+
+fn expect_identifier(tokens: &mut Peekable<Iter<Token>>, message: &str) -> Result<Rc<str>, String> {
+    match tokens.next_if(|token| matches!(token, Token::Identifier(_))) {
+        Some(Token::Identifier(name)) => Ok(name.clone()),
+        _ => Err(message.into()),
     }
 }
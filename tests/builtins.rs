@@ -0,0 +1,552 @@
+mod common;
+
+use common::{error, output, run, run_with};
+
+#[test]
+fn mutating_a_copy_leaves_the_original_alone() {
+    let source = r#"
+        a = [1, [2, 3]];
+        b = copy(a);
+        b[0] = 7;
+        b[1][0] = 9;
+        print a;
+        print b;
+        m = {"k": [1]};
+        n = copy(m);
+        n["k"][0] = 5;
+        print m;
+        print n;
+    "#;
+    assert_eq!(output(source), "[1, [2, 3]]\n[7, [9, 3]]\n{\"k\": [1]}\n{\"k\": [5]}\n");
+}
+
+#[test]
+fn user_functions_take_precedence_over_builtins() {
+    let source = r#"
+        func count(x) { return "mine " + x; }
+        func sum(a, b) { return a - b; }
+        func range(n) { return [n]; }
+        print count(3);
+        print sum(5, 2);
+        foreach (x in range(4)) { print x; }
+    "#;
+    assert_eq!(output(source), "mine 3\n3\n4\n");
+}
+
+#[test]
+fn a_variable_holding_a_function_shadows_a_builtin() {
+    let source = r#"
+        func twice(x) { return x * 2; }
+        len = twice;
+        print len(4);
+    "#;
+    assert_eq!(output(source), "8\n");
+}
+
+#[test]
+fn a_variable_holding_a_plain_value_does_not_hide_a_builtin() {
+    assert_eq!(output("len = 3; print len([1, 2]);"), "2\n");
+    assert!(error("print nope(1);").contains("Undefined function 'nope'"));
+}
+
+#[test]
+fn a_user_function_named_like_a_builtin_can_tail_call_itself() {
+    let source = r#"
+        func sum(n, total) {
+            if (n == 0) { return total; }
+            return sum(n - 1, total + n);
+        }
+        print sum(100000, 0);
+    "#;
+    assert_eq!(output(source), "5000050000\n");
+}
+
+#[test]
+fn panic_reports_its_message_and_fails() {
+    let run = run(r#"print 1; panic("boom"); print 2;"#);
+    assert!(!run.success);
+    assert_eq!(run.stdout, "1\n");
+    assert_eq!(run.stderr, "Error: boom\n");
+}
+
+#[test]
+fn repr_escapes_newlines_and_tabs() {
+    assert_eq!(output(r#"print repr("a\nb");"#), "\"a\\nb\"\n");
+    assert_eq!(output(r#"print repr("a\tb");"#), "\"a\\tb\"\n");
+}
+
+#[test]
+fn comparisons_produce_booleans() {
+    assert_eq!(output(r#"print typeof(1 < 2) == "boolean";"#), "true\n");
+    assert_eq!(output("print 2 >= 2; print 1 <= 0;"), "true\nfalse\n");
+}
+
+#[test]
+fn sort_orders_numbers_and_strings() {
+    assert_eq!(output("print sort([3, 1, 2]);"), "[1, 2, 3]\n");
+    assert_eq!(output(r#"print sort(["b", "c", "a"]);"#), "[\"a\", \"b\", \"c\"]\n");
+}
+
+#[test]
+fn sort_accepts_a_comparator() {
+    let source = "func descending(a, b) { return b - a; } print sort([1, 3, 2], descending);";
+    assert_eq!(output(source), "[3, 2, 1]\n");
+}
+
+#[test]
+fn reverse_handles_arrays_and_strings() {
+    assert_eq!(output("print reverse([1, 2, 3]);"), "[3, 2, 1]\n");
+    assert_eq!(output(r#"print reverse("abc");"#), "cba\n");
+    assert_eq!(output(r#"print reverse("héllo→");"#), "→olléh\n");
+}
+
+#[test]
+fn chars_splits_a_string_into_characters() {
+    assert_eq!(output(r#"print chars("abc");"#), "[\"a\", \"b\", \"c\"]\n");
+    let source = r#"s = "héllo→"; print len(chars(s)); print join(chars(s), "") == s;"#;
+    assert_eq!(output(source), "6\ntrue\n");
+}
+
+#[test]
+fn builtins_report_the_wrong_number_of_arguments() {
+    assert_eq!(error("len(1, 2);"), "Error: len() expects 1 argument, got 2\n");
+    assert_eq!(error("len();"), "Error: len() expects 1 argument, got 0\n");
+    assert_eq!(error("range();"), "Error: range() expects 1 to 3 arguments, got 0\n");
+    assert_eq!(error("range(1, 2, 3, 4);"), "Error: range() expects 1 to 3 arguments, got 4\n");
+    assert_eq!(error("fmt();"), "Error: fmt() expects at least 1 argument, got 0\n");
+}
+
+#[test]
+fn set_thousands_toggles_separators_in_printed_numbers() {
+    let source = "print 1234567; set_thousands(true); print 1234567; print -1234567.5;";
+    assert_eq!(output(source), "1234567\n1,234,567\n-1,234,567.5\n");
+    assert_eq!(output("set_thousands(true); set_thousands(false); print 1234567;"), "1234567\n");
+}
+
+#[test]
+fn debug_prints_the_variables_in_scope() {
+    let source = r#"a = 1; b = "s"; func f(z) { debug(); } f(5); print "after";"#;
+    let run = run_with(&[], source, "\n");
+    assert!(run.success);
+    assert_eq!(run.stdout, "after\n");
+    assert!(run.stderr.starts_with("[debug] variables in scope:\n"), "{}", run.stderr);
+    for line in ["[debug]   a = 1\n", "[debug]   b = \"s\"\n", "[debug]   z = 5\n"] {
+        assert!(run.stderr.contains(line), "missing {:?} in {}", line, run.stderr);
+    }
+}
+
+#[test]
+fn functions_lists_declared_functions_sorted() {
+    assert_eq!(output("func b() {} func a() {} print functions();"), "[\"a\", \"b\"]\n");
+}
+
+#[test]
+fn parse_json_builds_nested_values() {
+    let source = r#"
+        v = parse_json("{\"a\": [1, {\"b\": null}], \"c\": true}");
+        print v;
+        print v["a"][1]["b"];
+    "#;
+    assert_eq!(output(source), "{\"a\": [1, {\"b\": nil}], \"c\": true}\nnil\n");
+}
+
+#[test]
+fn parse_json_reports_malformed_input() {
+    assert_eq!(
+        error(r#"parse_json("{\"a\": }");"#),
+        "Error: Invalid JSON at position 6: unexpected character\n"
+    );
+    let source = r#"try { parse_json("[1, 2"); } catch (e) { print e; }"#;
+    assert_eq!(output(source), "Invalid JSON at position 5: unexpected end of input\n");
+}
+
+#[test]
+fn parse_json_rejects_deep_nesting_without_crashing() {
+    let source = r#"s = ""; repeat (1000) { s = s + "["; } parse_json(s);"#;
+    assert_eq!(error(source), "Error: Invalid JSON at position 200: nested too deeply\n");
+}
+
+#[test]
+fn to_json_round_trips_through_parse_json() {
+    let source = r#"
+        x = {"a": [1, 2.5, {"b": nil}], "c": "q\"s\n", "d": true};
+        print to_json(x);
+        print parse_json(to_json(x)) == x;
+    "#;
+    assert_eq!(
+        output(source),
+        "{\"a\":[1,2.5,{\"b\":null}],\"c\":\"q\\\"s\\n\",\"d\":true}\ntrue\n"
+    );
+}
+
+#[test]
+fn read_lines_splits_stdin_into_lines() {
+    let run = run_with(&[], "print read_lines();", "one\ntwo\n\nthree\n");
+    assert_eq!(run.stdout, "[\"one\", \"two\", \"\", \"three\"]\n");
+    let run = run_with(&[], "print read_lines(); print repr(read_all());", "");
+    assert_eq!(run.stdout, "[]\n\"\"\n");
+}
+
+#[test]
+fn read_all_returns_stdin_as_one_string() {
+    let run = run_with(&[], "print repr(read_all());", "a\nb\n");
+    assert_eq!(run.stdout, "\"a\\nb\\n\"\n");
+}
+
+#[test]
+fn format_sci_uses_scientific_notation() {
+    assert_eq!(output("print format_sci(12345, 2);"), "1.23e4\n");
+    assert_eq!(output("print format_sci(0.00012, 1);"), "1.2e-4\n");
+}
+
+#[test]
+fn flatten_removes_one_level_of_nesting() {
+    assert_eq!(output("print flatten([1, [2, [3, [4]]], 5]);"), "[1, 2, [3, [4]], 5]\n");
+}
+
+#[test]
+fn flatten_deep_removes_all_nesting() {
+    assert_eq!(output("print flatten_deep([1, [2, [3, [4]]], 5]);"), "[1, 2, 3, 4, 5]\n");
+}
+
+#[test]
+fn range_takes_a_step() {
+    assert_eq!(output("print range(0, 10, 2);"), "[0, 2, 4, 6, 8]\n");
+    assert_eq!(output("print range(5, 0, -1);"), "[5, 4, 3, 2, 1]\n");
+}
+
+#[test]
+fn range_rejects_a_zero_step() {
+    assert_eq!(error("range(0, 5, 0);"), "Error: range() step cannot be zero\n");
+}
+
+#[test]
+fn assert_eq_passes_on_equal_values() {
+    let source = r#"assert_eq(1 + 1, 2); assert_eq([1, [2]], [1, [2]]); print "ok";"#;
+    assert_eq!(output(source), "ok\n");
+}
+
+#[test]
+fn assert_eq_failure_shows_both_values() {
+    assert_eq!(error(r#"assert_eq("a", 1);"#), "Error: assert_eq failed: expected 1, got \"a\"\n");
+    assert_eq!(
+        error("assert_eq([1, 2], [1, 3]);"),
+        "Error: assert_eq failed: expected [1, 3], got [1, 2] (at [1]: 2 != 3)\n"
+    );
+}
+
+#[test]
+fn count_counts_substrings_without_overlap() {
+    assert_eq!(output(r#"print count("banana", "an"); print count("aaaa", "aa");"#), "2\n2\n");
+}
+
+#[test]
+fn count_counts_equal_array_elements() {
+    assert_eq!(output("print count([1, 2, 1, [1]], 1); print count([[1], [1]], [1]);"), "2\n2\n");
+}
+
+#[test]
+fn count_rejects_an_empty_needle() {
+    assert_eq!(error(r#"count("abc", "");"#), "Error: count() needle cannot be empty\n");
+}
+
+#[test]
+fn replace_substitutes_every_occurrence() {
+    assert_eq!(output(r#"print replace("hello world", "o", "0");"#), "hell0 w0rld\n");
+    assert_eq!(output(r#"print replace("aXa", "X", "long");"#), "alonga\n");
+}
+
+#[test]
+fn replace_rejects_an_empty_pattern() {
+    assert_eq!(
+        error(r#"replace("abc", "", "x");"#),
+        "Error: replace() cannot replace an empty string\n"
+    );
+}
+
+#[test]
+fn starts_with_checks_the_prefix() {
+    let source = r#"print starts_with("hello", "he"); print starts_with("hello", "lo");"#;
+    assert_eq!(output(source), "true\nfalse\n");
+}
+
+#[test]
+fn ends_with_checks_the_suffix() {
+    let source = r#"print ends_with("hello", "lo"); print ends_with("hello", "he");"#;
+    assert_eq!(output(source), "true\nfalse\n");
+}
+
+#[test]
+fn fmt_fills_placeholders_in_order() {
+    assert_eq!(output(r#"print fmt("{} + {} = {}", 1, 2, 3);"#), "1 + 2 = 3\n");
+}
+
+#[test]
+fn fmt_rejects_a_placeholder_count_mismatch() {
+    assert_eq!(
+        error(r#"fmt("{} {}", 1);"#),
+        "Error: fmt() template has 2 placeholders but 1 value was given\n"
+    );
+    assert_eq!(
+        error(r#"fmt("{}", 1, 2);"#),
+        "Error: fmt() template has 1 placeholder but 2 values were given\n"
+    );
+}
+
+#[test]
+fn fmt_indexed_placeholders_can_repeat_and_reorder() {
+    assert_eq!(output(r#"print fmt("{0}{0}", "ab");"#), "abab\n");
+    assert_eq!(output(r#"print fmt("{1} {0}", "a", "b");"#), "b a\n");
+}
+
+#[test]
+fn fmt_rejects_an_out_of_range_index() {
+    assert_eq!(
+        error(r#"fmt("{2}", 1);"#),
+        "Error: fmt() placeholder {2} is out of range for 1 value\n"
+    );
+}
+
+#[test]
+fn sign_and_clamp_handle_representative_values() {
+    assert_eq!(output("print sign(-5); print sign(0); print sign(3);"), "-1\n0\n1\n");
+    assert_eq!(output("print clamp(15, 0, 10); print clamp(-1, 0, 10);"), "10\n0\n");
+}
+
+#[test]
+fn clamp_rejects_reversed_bounds() {
+    assert_eq!(
+        error("clamp(1, 5, 0);"),
+        "Error: clamp() lower bound 5 is greater than upper bound 0\n"
+    );
+}
+
+#[test]
+fn trig_and_log_builtins_compute_known_values() {
+    assert_eq!(output("print sin(0); print cos(0); print log(E);"), "0\n1\n1\n");
+}
+
+#[test]
+fn log_rejects_non_positive_numbers() {
+    assert_eq!(error("log(0);"), "Error: log() requires a positive number, got 0\n");
+}
+
+#[test]
+fn log_rejects_nan() {
+    let source = "set_numeric_guard(false);\nnan = 0 * (1e308 * 10);\nlog10(nan);";
+    assert_eq!(error(source), "Error: log10() requires a positive number, got NaN\n");
+}
+
+#[test]
+fn round_to_rounds_to_decimal_places() {
+    assert_eq!(output("print round_to(2.567, 2); print round_to(2.561, 2);"), "2.57\n2.56\n");
+    assert_eq!(output("print round_to(3.14159, 2) == 3.14;"), "true\n");
+}
+
+#[test]
+fn round_to_zero_places_gives_an_integer() {
+    assert_eq!(output("print round_to(1234.5678, 0); print round_to(-2.5, 0);"), "1235\n-3\n");
+}
+
+#[test]
+fn eval_returns_the_value_of_an_expression() {
+    assert_eq!(output(r#"print eval("1 + 2");"#), "3\n");
+}
+
+#[test]
+fn eval_defines_variables_in_the_calling_scope() {
+    assert_eq!(output(r#"eval("y = 40;"); print y + 2;"#), "42\n");
+}
+
+#[test]
+fn zip_pairs_up_elements() {
+    assert_eq!(output(r#"print zip([1, 2], ["a", "b"]);"#), "[[1, \"a\"], [2, \"b\"]]\n");
+}
+
+#[test]
+fn zip_stops_at_the_shorter_array() {
+    assert_eq!(output("print zip([1, 2, 3], [4]); print zip([], [1]);"), "[[1, 4]]\n[]\n");
+}
+
+#[test]
+fn numeric_predicates_classify_representative_values() {
+    let source = r#"
+        set_numeric_guard(false);
+        inf = 1e308 * 10;
+        nan = 0 * inf;
+        print [is_int(2.0), is_int(2.5), is_int(-3), is_int(inf), is_int(nan)];
+        print [is_nan(nan), is_nan(1), is_nan(inf)];
+        print [is_inf(inf), is_inf(-inf), is_inf(1), is_inf(nan)];
+    "#;
+    assert_eq!(
+        output(source),
+        "[true, false, true, false, false]\n[true, false, false]\n[true, true, false, false]\n"
+    );
+}
+
+#[test]
+fn to_map_inverts_to_pairs() {
+    let source = r#"
+        m = {"a": 1, "b": [2]};
+        print to_pairs(m);
+        print to_map(to_pairs(m)) == m;
+    "#;
+    assert_eq!(output(source), "[[\"a\", 1], [\"b\", [2]]]\ntrue\n");
+}
+
+#[test]
+fn memoize_calls_the_underlying_function_once_per_argument() {
+    let source = r#"
+        calls = 0;
+        func fib(n) {
+            global calls;
+            calls = calls + 1;
+            if (n < 2) { return n; }
+            return mfib(n - 1) + mfib(n - 2);
+        }
+        mfib = memoize(fib);
+        print mfib(30);
+        print calls;
+    "#;
+    // The naive recursion would make 2,692,537 calls
+    assert_eq!(output(source), "832040\n31\n");
+}
+
+#[test]
+fn hex_bin_and_oct_format_integers() {
+    assert_eq!(output("print hex(255); print bin(10); print oct(8);"), "0xff\n0b1010\n0o10\n");
+}
+
+#[test]
+fn hex_rejects_non_integers() {
+    assert_eq!(error("hex(1.5);"), "Error: hex() requires a non-negative integer, got 1.5\n");
+}
+
+#[test]
+fn uid_returns_distinct_strings() {
+    let source = "a = uid(); b = uid(); print typeof(a); print a == b;";
+    assert_eq!(output(source), "string\nfalse\n");
+}
+
+#[test]
+fn diff_returns_nil_for_equal_values() {
+    let source = r#"
+        a = [1, {"name": "a", "tags": ["x"]}];
+        print diff(a, [1, {"name": "a", "tags": ["x"]}]);
+    "#;
+    assert_eq!(output(source), "nil\n");
+}
+
+#[test]
+fn diff_describes_the_path_to_the_first_mismatch() {
+    let source = r#"print diff([1, {"name": "a"}], [1, {"name": "b"}]);"#;
+    assert_eq!(output(source), "[1].name: \"a\" != \"b\"\n");
+}
+
+#[test]
+fn min_of_and_max_of_order_numbers_and_strings() {
+    let source = r#"
+        print min_of([3, 1, 2]);
+        print max_of([3, 1, 2]);
+        print min_of(["pear", "apple", "fig"]);
+        print max_of(["pear", "apple", "fig"]);
+    "#;
+    assert_eq!(output(source), "1\n3\napple\npear\n");
+}
+
+#[test]
+fn min_of_an_empty_array_is_an_error() {
+    assert_eq!(error("print min_of([]);"), "Error: min_of() of an empty array\n");
+}
+
+#[test]
+fn sum_and_product_fold_numbers() {
+    let source = "print sum([1, 2, 3]); print product([2, 3, 4]);\n\
+                  print sum([]); print product([]);";
+    assert_eq!(output(source), "6\n24\n0\n1\n");
+}
+
+#[test]
+fn group_by_groups_elements_by_key() {
+    let source = r#"
+        func parity(n) { if (is_int(n / 2)) { return "even"; } return "odd"; }
+        print group_by([1, 2, 3, 4, 5], parity);
+    "#;
+    assert_eq!(output(source), "{\"even\": [2, 4], \"odd\": [1, 3, 5]}\n");
+}
+
+#[test]
+fn group_by_keys_follow_map_access() {
+    let source = "func even(n) { return is_int(n / 2); }\nprint group_by([1, 2], even);";
+    assert_eq!(error(source), "Error: group_by() keys must be strings or numbers\n");
+    assert_eq!(error("m = {};\nprint m[true];"), "Error: Map keys must be strings or numbers\n");
+}
+
+#[test]
+fn pad_left_and_pad_right_fill_to_a_width() {
+    let source = r#"
+        print "[" + pad_left("7", 3) + "]";
+        print "[" + pad_right("ab", 4) + "]";
+        print pad_left("日", 3, "*");
+        print pad_right("ab", 4, ".");
+        print pad_left("abc", 2);
+    "#;
+    assert_eq!(output(source), "[  7]\n[ab  ]\n**日\nab..\nabc\n");
+}
+
+#[test]
+fn find_and_find_index_return_the_first_match() {
+    let source = "func big(n) { return n > 2; }\n\
+                  print find([1, 3, 5], big); print find_index([1, 3, 5], big);";
+    assert_eq!(output(source), "3\n1\n");
+}
+
+#[test]
+fn find_and_find_index_report_a_missing_element() {
+    let source = "func big(n) { return n > 9; }\n\
+                  print find([1, 3], big); print find_index([1, 3], big);";
+    assert_eq!(output(source), "nil\n-1\n");
+}
+
+#[test]
+fn unique_keeps_the_first_of_each_value() {
+    let source = r#"print unique([1, 2, 1, [3], [3], "a", "a"]); print unique([1, 2, 3]);"#;
+    assert_eq!(output(source), "[1, 2, [3], \"a\"]\n[1, 2, 3]\n");
+    let message = "Error: unique() does not accept arguments of type (number)\n";
+    assert_eq!(error("print unique(1);"), message);
+}
+
+#[test]
+fn a_collection_that_contains_itself_does_not_recurse_forever() {
+    let source = r#"
+        a = [0];
+        a[0] = a;
+        print a;
+        b = copy(a);
+        print b[0] == b;
+        print a == a; print a == b; print diff(a, b);
+        m = {"x": 1};
+        m["self"] = m;
+        print m;
+    "#;
+    assert_eq!(output(source), "[[...]]\ntrue\ntrue\ntrue\nnil\n{\"self\": {...}, \"x\": 1}\n");
+    assert_eq!(
+        error("a = [0]; a[0] = a; print to_json(a);"),
+        "Error: Cannot serialize a value that contains itself to JSON\n"
+    );
+    assert_eq!(
+        error("a = [0]; a[0] = a; print flatten_deep(a);"),
+        "Error: flatten_deep() of an array that contains itself\n"
+    );
+}
+
+#[test]
+fn a_sort_comparator_may_modify_the_array_being_sorted() {
+    let source = r#"
+        values = [3, 1, 2];
+        func ascending(a, b) { values[0] = 9; return a - b; }
+        print sort(values, ascending);
+        print values;
+    "#;
+    assert_eq!(output(source), "[1, 2, 3]\n[9, 1, 2]\n");
+}
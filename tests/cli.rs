@@ -0,0 +1,163 @@
+mod common;
+
+use common::{output, run, run_with};
+
+/// Formats `source` with `--format` and the given extra flags.
+fn format_with(flags: &[&str], source: &str) -> String {
+    let mut args = vec!["--format"];
+    args.extend(flags);
+    let run = run_with(&args, source, "");
+    assert!(run.success, "formatting failed: {}", run.stderr);
+    run.stdout
+}
+
+const SAMPLE: &str = r#"
+// A sample program
+func greet(name) {   // trailing comment
+  print "Hello, " + name;
+}
+x = 1 + 2 * 3;   if (x > 5) { print x; } else { print "small"; }
+while (x > 0) { x = x - 1; }
+greet("World");
+"#;
+
+#[test]
+fn formatting_is_idempotent() {
+    let formatted = format_with(&[], SAMPLE);
+    assert_eq!(format_with(&[], &formatted), formatted);
+    assert!(formatted.starts_with("func greet(name) {\n    print \"Hello, \" + name;\n}\n"));
+}
+
+#[test]
+fn formatting_preserves_behavior() {
+    let formatted = format_with(&[], SAMPLE);
+    assert_eq!(output(&formatted), output(SAMPLE));
+}
+
+#[test]
+fn stats_reports_tokens_and_nodes() {
+    let run = run_with(&["--stats"], "x = 1 + 2;\nif (x > 2) { print x; }\n", "");
+    assert!(run.success);
+    assert_eq!(run.stdout, "3\n");
+    assert_eq!(run.stderr, "Tokens: 18\nAST nodes: 10\n");
+}
+
+#[test]
+fn sources_without_statements_run_silently() {
+    for source in ["", "  \n\t\r\n", "// only a comment\n  // and another"] {
+        let run = run(source);
+        assert!(run.success, "{:?} failed: {}", source, run.stderr);
+        assert_eq!((run.stdout.as_str(), run.stderr.as_str()), ("", ""), "{:?}", source);
+        assert_eq!(format_with(&[], source), "");
+    }
+}
+
+#[test]
+fn indent_sets_the_indentation_unit() {
+    let source = "func f(x) {\nif (x) { print 1; }\n}\n";
+    assert_eq!(
+        format_with(&["--indent", "2"], source),
+        "func f(x) {\n  if (x) {\n    print 1;\n  }\n}\n"
+    );
+    assert_eq!(
+        format_with(&["--indent", "tab"], source),
+        "func f(x) {\n\tif (x) {\n\t\tprint 1;\n\t}\n}\n"
+    );
+    assert_eq!(
+        format_with(&[], source),
+        "func f(x) {\n    if (x) {\n        print 1;\n    }\n}\n"
+    );
+}
+
+#[test]
+fn profile_counts_calls_per_function() {
+    let source = "func f() { return 1; } i = 0; while (i < 5) { f(); i = i + 1; } func g() {} g();";
+    let run = run_with(&["--profile"], source, "");
+    assert!(run.success);
+    assert_eq!(run.stderr, "Profile:\n  f: 5 calls\n  g: 1 call\n");
+}
+
+#[test]
+fn max_iters_stops_an_infinite_loop() {
+    let run = run_with(&["--max-iters", "100"], "while (true) { }", "");
+    assert!(!run.success);
+    assert_eq!(run.stderr, "Error: Loop exceeded the maximum of 100 iterations\n");
+}
+
+#[test]
+fn max_iters_allows_loops_within_the_limit() {
+    let run = run_with(&["--max-iters", "3"], "repeat (3) { print 1; }", "");
+    assert!(run.success);
+    assert_eq!(run.stdout, "1\n1\n1\n");
+}
+
+#[test]
+fn empty_blocks_format_and_reparse() {
+    let source = "func f() {}\nif (true) {} else {}\nwhile (false) {}\nprint f();\n";
+    let formatted = format_with(&[], source);
+    assert_eq!(
+        formatted,
+        "func f() {\n}\nif (true) {\n} else {\n}\nwhile (false) {\n}\nprint f();\n"
+    );
+    assert_eq!(output(&formatted), "0\n");
+}
+
+#[test]
+fn trace_logs_each_statement() {
+    let source = "x = 1;\nif (x > 0) { print x; }\nfunc f() { return 2; }\nprint f();\n";
+    let run = run_with(&["--trace"], source, "");
+    assert!(run.success);
+    assert_eq!(run.stdout, "1\n2\n");
+    assert_eq!(
+        run.stderr,
+        "[trace] x = 1;\n[trace] if (x > 0) {\n[trace]   print x;\n[trace] func f() {\n\
+         [trace] print f();\n[trace]   return 2;\n"
+    );
+}
+
+#[test]
+fn strict_halts_on_an_undefined_read() {
+    let run = run_with(&["--strict"], "print 1; print y; print 2;", "");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "1\n");
+    assert_eq!(run.stderr, "Error: Undefined variable 'y'\n");
+}
+
+#[test]
+fn strict_still_lets_assignment_create_variables() {
+    let run = run_with(&["--strict"], "x = 1; if (true) { y = x + 1; print y; }", "");
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.stdout, "2\n");
+}
+
+#[test]
+fn an_undefined_read_is_only_a_warning_without_strict() {
+    let run = run("print y; print 2;");
+    assert!(run.success);
+    assert_eq!(run.stdout, "0\n2\n");
+    assert_eq!(run.stderr, "Error: Undefined variable 'y'\n");
+}
+
+#[test]
+fn check_flags_a_read_of_a_misspelled_variable() {
+    let source = "total = 0;\nforeach (x in [1, 2]) { total = total + x; }\nprint totl;\n\
+                  func f(a) { return a + b; }";
+    let run = run_with(&["--check"], source, "");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "");
+    assert_eq!(
+        run.stderr,
+        "Warning: 'totl' is read but never assigned\n\
+         Warning: 'b' is read in function 'f' but never assigned\n\
+         Error: 2 problems found\n"
+    );
+}
+
+#[test]
+fn check_accepts_correctly_assigned_variables() {
+    let source = "count = 1;\nfunc f(n) { let y = n; return y + count; }\nprint f(count);\n\
+                  foreach (i in [1]) { print i; }\ntry { x = 1; } catch (e) { print e; }";
+    let run = run_with(&["--check"], source, "");
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!((run.stdout.as_str(), run.stderr.as_str()), ("", ""));
+}
@@ -0,0 +1,80 @@
+//! Runs juul scripts through the built binary and captures what they print.
+
+// Each test file uses its own subset of these helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What a finished run of the interpreter left behind.
+pub struct Run {
+    pub stdout: String,
+    pub stderr: String,
+    pub success: bool,
+}
+
+/// Runs `source` as a script with no flags and empty input.
+pub fn run(source: &str) -> Run {
+    run_with(&[], source, "")
+}
+
+/// Runs `source` as a script with the given flags, feeding `input` to stdin.
+pub fn run_with(flags: &[&str], source: &str, input: &str) -> Run {
+    let dir = script_dir(&[("main.juul", source)]);
+    let run = run_file(flags, &dir.join("main.juul"), input);
+    fs::remove_dir_all(dir).ok();
+    run
+}
+
+/// Creates a fresh directory holding the given files, for scripts that
+/// import one another.
+pub fn script_dir(files: &[(&str, &str)]) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "juul-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir).expect("could not create the script directory");
+    for (name, source) in files {
+        fs::write(dir.join(name), source).expect("could not write the script");
+    }
+    dir
+}
+
+/// Runs the script at `path` with the given flags, feeding `input` to stdin.
+pub fn run_file(flags: &[&str], path: &Path, input: &str) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_juul"))
+        .args(flags)
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start juul");
+    // A script that never reads its input may exit before it is written
+    child.stdin.take().unwrap().write_all(input.as_bytes()).ok();
+    let output = child.wait_with_output().expect("juul did not finish");
+    Run {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+        success: output.status.success(),
+    }
+}
+
+/// Runs `source`, expecting it to succeed, and returns what it printed.
+pub fn output(source: &str) -> String {
+    let run = run(source);
+    assert!(run.success, "script failed: {}", run.stderr);
+    run.stdout
+}
+
+/// Runs `source`, expecting it to fail, and returns the error it reported.
+pub fn error(source: &str) -> String {
+    let run = run(source);
+    assert!(!run.success, "script succeeded, printing: {}", run.stdout);
+    run.stderr
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{run_file, script_dir};
+use std::fs;
+
+#[test]
+fn a_script_knows_its_own_path() {
+    let dir = script_dir(&[("where.juul", "print script_path(); print script_dir();")]);
+    let path = fs::canonicalize(dir.join("where.juul")).unwrap();
+    let run = run_file(&[], &dir.join("where.juul"), "");
+    assert!(run.success, "{}", run.stderr);
+    let expected = format!("{}\n{}\n", path.display(), path.parent().unwrap().display());
+    assert_eq!(run.stdout, expected);
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn an_imported_function_can_be_called() {
+    let dir = script_dir(&[
+        ("lib.juul", "func greet(name) { return \"Hello, \" + name; }"),
+        ("main.juul", "import \"lib.juul\";\nprint greet(\"World\");"),
+    ]);
+    let run = run_file(&[], &dir.join("main.juul"), "");
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.stdout, "Hello, World\n");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn a_cyclic_import_is_an_error() {
+    let dir = script_dir(&[("a.juul", "import \"b.juul\";"), ("b.juul", "import \"a.juul\";")]);
+    let run = run_file(&[], &dir.join("a.juul"), "");
+    assert!(!run.success);
+    assert_eq!(run.stderr, "Error: Cyclic import of 'a.juul'\n");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn import_errors_name_the_imported_file() {
+    let dir = script_dir(&[("bad.juul", "x = ;"), ("main.juul", "import \"bad.juul\";")]);
+    let run = run_file(&[], &dir.join("main.juul"), "");
+    assert_eq!(run.stderr, "Error: In 'bad.juul': Line 1, column 5: Expected an expression.\n");
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn one_file_can_be_imported_under_two_namespaces() {
+    let dir = script_dir(&[
+        (
+            "counter.juul",
+            "count = 0;\nfunc next() { global count; count = count + 1; return count; }",
+        ),
+        (
+            "main.juul",
+            "import \"counter.juul\" as a;\nimport \"counter.juul\" as b;\n\
+             a.next();\nprint a.next();\nprint b.next();",
+        ),
+    ]);
+    let run = run_file(&[], &dir.join("main.juul"), "");
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.stdout, "2\n1\n");
+    fs::remove_dir_all(dir).ok();
+}
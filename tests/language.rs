@@ -0,0 +1,700 @@
+mod common;
+
+use common::{error, output, run};
+
+#[test]
+fn methods_can_be_called_on_strings_and_arrays() {
+    assert_eq!(output(r#"print "hi".upper(); print [1, 2, 3].len();"#), "HI\n3\n");
+}
+
+#[test]
+fn a_top_level_return_is_rejected_before_running() {
+    let run = run("print 1;\nreturn;\nprint 2;");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "");
+    assert_eq!(run.stderr, "Error: Line 2, column 1: 'return' outside of a function.\n");
+}
+
+#[test]
+fn slices_take_a_range_of_elements() {
+    let source = "a = [1, 2, 3, 4, 5]; print a[1:3]; print a[-2:];";
+    assert_eq!(output(source), "[2, 3]\n[4, 5]\n");
+}
+
+#[test]
+fn slice_bounds_may_be_left_out() {
+    let source = "a = [1, 2, 3, 4, 5]; print a[:2]; print a[3:]; print a[:];";
+    assert_eq!(output(source), "[1, 2]\n[4, 5]\n[1, 2, 3, 4, 5]\n");
+}
+
+#[test]
+fn strings_slice_by_character() {
+    assert_eq!(output(r#"print "héllo"[1:4];"#), "éll\n");
+}
+
+#[test]
+fn in_tests_array_membership_by_equality() {
+    assert_eq!(output("print 2 in [1, 2, 3]; print 5 in [1, 2];"), "true\nfalse\n");
+    assert_eq!(output("print [1] in [[1], [2]];"), "true\n");
+}
+
+#[test]
+fn in_tests_map_keys() {
+    assert_eq!(output(r#"m = {"a": 1}; print "a" in m; print "b" in m;"#), "true\nfalse\n");
+}
+
+#[test]
+fn catch_intercepts_a_division_by_zero() {
+    let source = r#"try { x = 1 / 0; print "unreached"; } catch (e) { print "caught"; print e; }"#;
+    let output = output(source);
+    assert!(output.starts_with("caught\nDivision by zero"), "{}", output);
+}
+
+#[test]
+fn an_error_outside_try_stops_the_program() {
+    let run = run("print 1; x = 1 / 0; print 2;");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "1\n");
+    assert!(run.stderr.starts_with("Error: Division by zero"), "{}", run.stderr);
+}
+
+#[test]
+fn a_thrown_string_is_caught_as_is() {
+    let source = r#"try { throw "bad"; } catch (e) { print "got " + e; }"#;
+    assert_eq!(output(source), "got bad\n");
+}
+
+#[test]
+fn an_uncaught_throw_reports_the_value() {
+    let run = run(r#"print 1; throw "bad";"#);
+    assert!(!run.success);
+    assert_eq!(run.stdout, "1\n");
+    assert_eq!(run.stderr, "Error: Uncaught exception: \"bad\"\n");
+}
+
+#[test]
+fn let_shadows_an_outer_variable() {
+    let source = "x = 1; if (true) { let x = 2; print x; } print x;";
+    assert_eq!(output(source), "2\n1\n");
+}
+
+#[test]
+fn assignment_mutates_an_outer_variable() {
+    assert_eq!(output("x = 1; if (true) { x = 3; } print x;"), "3\n");
+}
+
+#[test]
+fn self_tail_calls_do_not_grow_the_stack() {
+    let source = r#"
+        func countdown(n) {
+            if (n == 0) { return "done"; }
+            return countdown(n - 1);
+        }
+        print countdown(1000000);
+    "#;
+    assert_eq!(output(source), "done\n");
+}
+
+#[test]
+fn the_coalesce_fallback_is_skipped_for_a_non_nil_value() {
+    let source = r#"
+        n = 0;
+        func bump() { global n; n = n + 1; return 9; }
+        print 5 ?? bump();
+        print false ?? bump();
+        print n;
+    "#;
+    assert_eq!(output(source), "5\nfalse\n0\n");
+}
+
+#[test]
+fn the_coalesce_fallback_replaces_nil() {
+    assert_eq!(output(r#"print nil ?? "fallback";"#), "fallback\n");
+}
+
+#[test]
+fn optional_indexing_on_nil_yields_nil() {
+    assert_eq!(output("m = nil; print m?[\"a\"];"), "nil\n");
+}
+
+#[test]
+fn optional_indexing_reads_a_real_map() {
+    assert_eq!(output(r#"m = {"a": 1}; print m?["a"];"#), "1\n");
+}
+
+#[test]
+fn trailing_commas_are_allowed_in_lists() {
+    let source = r#"
+        func add(a, b,) { return a + b; }
+        print add(1, 2,);
+        print [1, 2,];
+        print {"a": 1,};
+    "#;
+    assert_eq!(output(source), "3\n[1, 2]\n{\"a\": 1}\n");
+}
+
+#[test]
+fn a_lone_comma_is_still_an_error() {
+    let run = run("print [1,, 2];");
+    assert!(!run.success);
+    assert_eq!(run.stderr, "Error: Line 1, column 10: Expected an expression.\n");
+}
+
+#[test]
+fn a_function_can_update_a_declared_global() {
+    let source = r#"
+        count = 0;
+        func bump() { global count; count = count + 1; }
+        bump();
+        bump();
+        print count;
+    "#;
+    assert_eq!(output(source), "2\n");
+}
+
+#[test]
+fn assignment_in_a_function_is_local_without_global() {
+    assert_eq!(output("count = 0; func f() { count = 5; } f(); print count;"), "0\n");
+}
+
+#[test]
+fn parallel_assignment_binds_positionally() {
+    let source = "a, b, c = 1, 2, 3; print [a, b, c]; a, b = b, a; print [a, b];";
+    assert_eq!(output(source), "[1, 2, 3]\n[2, 1]\n");
+    assert_eq!(output("a = b = c = 0; print [a, b, c];"), "[0, 0, 0]\n");
+}
+
+#[test]
+fn parallel_assignment_rejects_a_count_mismatch() {
+    let run = run("a, b = 1, 2, 3;");
+    assert!(!run.success);
+    assert_eq!(run.stderr, "Error: Line 1, column 15: Cannot assign 3 values to 2 targets.\n");
+}
+
+#[test]
+fn hex_escapes_insert_characters() {
+    assert_eq!(output(r#"print "\x41\x62";"#), "Ab\n");
+}
+
+#[test]
+fn malformed_hex_escapes_are_rejected() {
+    let run = run(r#"print "\xZ1";"#);
+    assert!(!run.success);
+    assert_eq!(
+        run.stderr,
+        "Error: Line 1, column 10: Invalid '\\x' escape: expected two hex digits.\n"
+    );
+}
+
+#[test]
+fn conditions_are_evaluated_exactly_once() {
+    let source = r#"
+        calls = 0;
+        func check(v) { global calls; calls = calls + 1; return v; }
+        if (check(true)) { print "then"; }
+        print calls;
+        if (check(false)) { print "no"; } else if (check(true)) { print "else if"; }
+        print calls;
+        func twice(x) { return x + x; }
+        if (twice(check(1)) == 2) { print "argument"; }
+        print calls;
+        func g() { guard check(true) else { return 1; } return 2; }
+        g();
+        print calls;
+        n = 0;
+        while (check(n < 3)) { n = n + 1; }
+        print calls;
+    "#;
+    assert_eq!(output(source), "then\n1\nelse if\n3\nargument\n4\n5\n9\n");
+}
+
+#[test]
+fn plus_concatenates_arrays() {
+    assert_eq!(output("a = [1, 2]; print a + [3]; print a;"), "[1, 2, 3]\n[1, 2]\n");
+}
+
+#[test]
+fn plus_rejects_an_array_and_a_number() {
+    let run = run("print [1] + 2;");
+    assert!(!run.success);
+    assert_eq!(run.stderr, "Error: Cannot add array and number\n");
+}
+
+#[test]
+fn postfix_operations_chain() {
+    let source = r#"
+        arr = [[1, 2], [3, 4]];
+        print arr[0][1];
+        func pair() { return [5, [6, 7]]; }
+        print pair()[1][0];
+        print pair()[1].len();
+        print "abc".upper()[1];
+    "#;
+    assert_eq!(output(source), "2\n6\n2\nB\n");
+}
+
+#[test]
+fn arithmetic_overflow_is_an_error() {
+    let run = run("print 1e308 * 10;");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "");
+    assert_eq!(run.stderr, "Error: Arithmetic '*' produced a non-finite result (inf)\n");
+}
+
+#[test]
+fn nan_from_a_guarded_division_is_never_printed() {
+    let run = run("print 0 * (1 / 0);");
+    assert!(!run.success);
+    assert_eq!(run.stdout, "");
+    assert!(run.stderr.starts_with("Error: Division by zero"), "{}", run.stderr);
+}
+
+#[test]
+fn pipe_calls_a_named_function() {
+    let source = r#"
+        func double(x) { return x * 2; }
+        func add(a, b) { return a + b; }
+        print 5 |> double;
+        print [1, 2, 3] |> len;
+        print 5 |> add(1) |> double;
+    "#;
+    assert_eq!(output(source), "10\n3\n12\n");
+}
+
+#[test]
+fn pipe_calls_any_callable_expression() {
+    let source = r#"
+        func double(x) { return x * 2; }
+        func add(a, b) { return a + b; }
+        func maker() { return add; }
+        fs = [double, add];
+        m = {"f": double};
+        f = nil;
+        print 4 |> fs[0];
+        print 4 |> fs[1](10);
+        print 3 |> m["f"];
+        print 3 |> (f ?? double);
+        print 1 |> maker()(2);
+        print "an" |> "banana".count();
+    "#;
+    assert_eq!(output(source), "8\n14\n6\n6\n3\n2\n");
+}
+
+#[test]
+fn negative_zero_equals_zero() {
+    let source = "print -0 == 0; print -0 < 0; print -0; print 0 * -1;";
+    assert_eq!(output(source), "true\nfalse\n0\n0\n");
+}
+
+#[test]
+fn division_by_zero_names_the_sign_of_the_zero() {
+    assert_eq!(error("print 1 / 0;"), "Error: Division by zero\n");
+    assert_eq!(error("print 1 / -0;"), "Error: Division by zero (negative zero)\n");
+    let source = "z = 0 * -1; print -1 / z;";
+    assert_eq!(error(source), "Error: Division by zero (negative zero)\n");
+}
+
+#[test]
+fn nan_is_unequal_to_everything_and_unordered() {
+    let source = r#"
+        set_numeric_guard(false);
+        n = 0 * (1e308 * 10);
+        print n == n;
+        print n != n;
+        print n < 1;
+        print n > 1;
+    "#;
+    assert_eq!(output(source), "false\ntrue\nfalse\nfalse\n");
+}
+
+#[test]
+fn foreach_over_a_range_is_lazy() {
+    let source = "total = 0; foreach (i in range(0, 1000000)) { total = total + i; } print total;";
+    assert_eq!(output(source), "499999500000\n");
+    // Materializing this range would exhaust memory long before the break
+    let source = "foreach (i in range(0, 1e15)) { if (i == 3) { break; } print i; }";
+    assert_eq!(output(source), "0\n1\n2\n");
+}
+
+#[test]
+fn guard_passes_through_when_the_condition_holds() {
+    let source = "func f(x) { guard x > 0 else { return \"no\"; } return \"yes\"; } print f(1);";
+    assert_eq!(output(source), "yes\n");
+}
+
+#[test]
+fn guard_runs_its_else_block_when_the_condition_fails() {
+    let source = "func f(x) { guard x > 0 else { return \"no\"; } return \"yes\"; } print f(-1);";
+    assert_eq!(output(source), "no\n");
+}
+
+#[test]
+fn guard_can_leave_a_loop_or_the_program() {
+    let source = r#"
+        foreach (x in [1, 2, 3, 4]) {
+            guard x != 2 else { continue; }
+            guard x < 4 else { break; }
+            print x;
+        }
+        outer: while (true) {
+            foreach (x in [1]) { guard false else { break outer; } }
+        }
+        guard false else { stop; }
+        print "unreached";
+    "#;
+    assert_eq!(output(source), "1\n3\n");
+}
+
+#[test]
+fn guard_must_not_fall_through() {
+    let run = run("guard true else { print 1; }");
+    assert!(!run.success);
+    assert!(run.stderr.ends_with(
+        "The else block of 'guard' must end with 'return', 'throw', 'break', 'continue' or \
+         'stop'.\n"
+    ));
+    assert!(error("guard true else { break; }").ends_with("'break' outside of a loop.\n"));
+}
+
+#[test]
+fn raw_strings_keep_backslashes() {
+    assert_eq!(output(r#"print r"C:\new\t";"#), "C:\\new\\t\n");
+    assert_eq!(output("print r\"line1\nline2\";"), "line1\nline2\n");
+}
+
+#[test]
+fn hashed_raw_strings_can_hold_quotes() {
+    assert_eq!(output(r###"print r#"say "hi" \n"#;"###), "say \"hi\" \\n\n");
+}
+
+#[test]
+fn parse_errors_report_where_they_were_found() {
+    assert_eq!(
+        error("print (1 + 2;\n"),
+        "Error: Line 1, column 13: Expected token RightParen, found Semicolon\n"
+    );
+    assert_eq!(
+        error("x = 1\nprint x;"),
+        "Error: Line 2, column 1: Expected token Semicolon, found Print\n"
+    );
+    assert_eq!(error("print 1 +* 2;"), "Error: Line 1, column 10: Expected an expression.\n");
+    assert_eq!(error("x = 3;\n  ) ;"), "Error: Line 2, column 3: Unexpected token in statement.\n");
+}
+
+#[test]
+fn a_nested_return_outside_a_function_is_reported_at_the_return() {
+    let source = "func f() {\n  if (true) { return 1; }\n}\nwhile (true) { return; }";
+    assert_eq!(error(source), "Error: Line 4, column 16: 'return' outside of a function.\n");
+    assert_eq!(
+        error(r#"func f() { return eval("return 2;"); } f();"#),
+        "Error: In eval(): Line 1, column 1: 'return' outside of a function.\n"
+    );
+}
+
+#[test]
+fn empty_blocks_run_as_no_ops() {
+    let source = r#"
+        func f() {}
+        print f();
+        if (true) {} else {}
+        if (false) {} else {}
+        x = 0;
+        while (x < 2) { x = x + 1; }
+        while (false) {}
+        foreach (i in [1, 2]) {}
+        repeat (2) {}
+        try {} catch (e) {}
+        defer {}
+        print "end";
+    "#;
+    assert_eq!(output(source), "0\nend\n");
+}
+
+#[test]
+fn functions_stored_in_collections_can_be_called() {
+    let source = r#"
+        func double(x) { return x * 2; }
+        func inc(x) { return x + 1; }
+        m = {"d": double};
+        a = [double, inc];
+        print m["d"](4);
+        print a[1](4);
+        print a[0](a[1](1));
+    "#;
+    assert_eq!(output(source), "8\n5\n4\n");
+}
+
+#[test]
+fn foreach_can_bind_the_index() {
+    let source = r#"foreach (i, x in ["a", "b", "c"]) { print fmt("{}: {}", i, x); }"#;
+    assert_eq!(output(source), "0: a\n1: b\n2: c\n");
+}
+
+#[test]
+fn deeply_nested_expressions_are_rejected_gracefully() {
+    let sources = [
+        format!("x = {}1{};", "(".repeat(100_000), ")".repeat(100_000)),
+        format!("x = {}1;", "-".repeat(100_000)),
+        format!("x = {}1;", "1 + ".repeat(100_000)),
+        format!("x = f{};", "(1)".repeat(100_000)),
+        format!("x = a{};", "[0]".repeat(100_000)),
+    ];
+    for source in &sources {
+        let run = run(source);
+        assert!(!run.success);
+        assert!(
+            run.stderr.ends_with("Expression too deeply nested.\n"),
+            "{}",
+            run.stderr
+        );
+    }
+}
+
+#[test]
+fn moderately_long_operator_chains_still_run() {
+    let source = format!("print {}1;", "1 + ".repeat(150));
+    assert_eq!(output(&source), "151\n");
+}
+
+#[test]
+fn the_comma_operator_evaluates_left_to_right() {
+    let source = "func say(x) { print x; return x; } v = (say(1), say(2), say(3));";
+    assert_eq!(output(source), "1\n2\n3\n");
+}
+
+#[test]
+fn the_comma_operator_yields_the_last_value() {
+    assert_eq!(output("v = (1, 2, 3); print v; print (1, 2) + 10;"), "3\n12\n");
+}
+
+#[test]
+fn defers_run_last_first() {
+    let source = r#"
+        func g() { defer { print "first"; } defer { print "second"; } print "body"; }
+        g();
+    "#;
+    assert_eq!(output(source), "body\nsecond\nfirst\n");
+}
+
+#[test]
+fn defers_run_on_early_return() {
+    let source = r#"
+        func h(x) {
+            defer { print "cleanup"; }
+            if (x) { return "early"; }
+            print "late";
+            return "end";
+        }
+        print h(true);
+        print h(false);
+    "#;
+    assert_eq!(output(source), "cleanup\nearly\nlate\ncleanup\nend\n");
+}
+
+#[test]
+fn a_pending_defer_keeps_a_recursive_return_in_order() {
+    let source = r#"
+        func f(n) {
+            defer { print "leaving " + n; }
+            if (n == 0) { return 0; }
+            return f(n - 1);
+        }
+        f(2);
+    "#;
+    assert_eq!(output(source), "leaving 0\nleaving 1\nleaving 2\n");
+}
+
+#[test]
+fn a_finished_defer_does_not_prevent_tail_calls() {
+    let source = r#"
+        func count(n) {
+            if (n == 0) { return "done"; }
+            if (n == 1) { defer { print "last"; } }
+            return count(n - 1);
+        }
+        print count(100000);
+    "#;
+    assert_eq!(output(source), "last\ndone\n");
+}
+
+#[test]
+fn a_labeled_break_leaves_both_loops() {
+    let source = r#"
+        outer: foreach (i in [1, 2, 3]) {
+            foreach (j in [1, 2, 3]) {
+                if (j == 2) { break outer; }
+                print fmt("{} {}", i, j);
+            }
+        }
+        print "out";
+    "#;
+    assert_eq!(output(source), "1 1\nout\n");
+}
+
+#[test]
+fn a_labeled_continue_moves_to_the_next_outer_iteration() {
+    let source = r#"
+        outer: foreach (i in [1, 2]) {
+            foreach (j in [1, 2, 3]) {
+                if (j == 2) { continue outer; }
+                print fmt("{} {}", i, j);
+            }
+            print "never";
+        }
+    "#;
+    assert_eq!(output(source), "1 1\n2 1\n");
+}
+
+#[test]
+fn an_unknown_label_is_rejected() {
+    let source = "while (true) { break nope; }";
+    assert_eq!(error(source), "Error: Line 1, column 22: Unknown loop label 'nope'.\n");
+}
+
+#[test]
+fn if_and_else_accept_a_single_statement_without_braces() {
+    assert_eq!(output("if (1 < 2) print \"yes\"; else print \"no\";"), "yes\n");
+    assert_eq!(output("if (1 > 2) print \"yes\"; else print \"no\";"), "no\n");
+    assert_eq!(output("if (1 > 2) print \"yes\";\nprint \"after\";"), "after\n");
+}
+
+#[test]
+fn a_dangling_else_belongs_to_the_nearest_if() {
+    assert_eq!(output("if (true) if (false) print \"a\"; else print \"b\";"), "b\n");
+    let source = "if (false) if (true) print \"a\"; else print \"b\";\nprint \"c\";";
+    assert_eq!(output(source), "c\n");
+}
+
+#[test]
+fn error_columns_count_characters_rather_than_bytes() {
+    let expected = "Error: Line 1, column 17: Invalid '\\x' escape: expected two hex digits.\n";
+    assert_eq!(error(r#"print "日本" + "\xZZ";"#), expected);
+    assert_eq!(error(r#"print "ab" + "\xZZ";"#), expected);
+}
+
+#[test]
+fn stop_ends_the_program_successfully() {
+    let result = run("print 1;\nif (true) { stop; }\nprint 2;");
+    assert!(result.success, "{}", result.stderr);
+    assert_eq!(result.stdout, "1\n");
+}
+
+#[test]
+fn unary_operators_bind_tighter_than_binary_ones() {
+    let source = "print -2 * 3; print - -2; print -(2 + 3) * 2;\n\
+                  print 10 - -3; print -len([1, 2]) + 1; print !true == false;";
+    assert_eq!(output(source), "-6\n2\n-10\n13\n-1\ntrue\n");
+}
+
+#[test]
+fn indexing_a_string_gives_a_single_character() {
+    assert_eq!(output(r#"print "hello"[0]; print "hello"[-1]; print "日本語"[1];"#), "h\no\n本\n");
+    assert_eq!(error(r#"print "hello"[5];"#), "Error: String index 5 out of bounds\n");
+}
+
+#[test]
+fn assignment_reaches_into_nested_collections() {
+    let source = r#"
+        data = {"users": [{"name": "a"}], "grid": [[1, 2], [3, 4]]};
+        data["grid"][1][0] = 9;
+        data["users"][0]["name"] = "b";
+        print data["grid"];
+        print data["users"];
+    "#;
+    assert_eq!(output(source), "[[1, 2], [9, 4]]\n[{\"name\": \"b\"}]\n");
+}
+
+#[test]
+fn code_after_a_return_is_reported_as_unreachable() {
+    let result = run("func f() { return 1; print 2; }\nprint f();");
+    assert!(result.success, "{}", result.stderr);
+    assert_eq!(result.stdout, "1\n");
+    assert_eq!(
+        result.stderr,
+        "Warning: code after 'return' in function 'f' is unreachable: print 2;\n"
+    );
+}
+
+#[test]
+fn a_final_return_is_not_reported() {
+    let result = run("func g() { if (true) { print 1; } return 2; }\nprint g();");
+    assert!(result.success);
+    assert_eq!((result.stdout.as_str(), result.stderr.as_str()), ("1\n2\n", ""));
+}
+
+#[test]
+fn a_known_string_prefix_is_applied() {
+    assert_eq!(output(r#"data = json"{\"a\": [1, 2]}"; print data["a"][1];"#), "2\n");
+    assert_eq!(output(r#"print r"a\nb";"#), "a\\nb\n");
+}
+
+#[test]
+fn an_unknown_string_prefix_is_an_error() {
+    assert_eq!(error(r#"print date"2024-01-01";"#), "Error: Unknown string prefix 'date'\n");
+}
+
+#[test]
+fn repeat_runs_its_block_the_given_number_of_times() {
+    let source = "repeat (3) { print \"hi\"; }\nrepeat (0) { print \"never\"; }\nprint \"done\";";
+    assert_eq!(output(source), "hi\nhi\nhi\ndone\n");
+}
+
+#[test]
+fn functions_compare_by_identity() {
+    let source = "func f() {}\nfunc g() {}\nh = f;\nprint f == f; print h == f; print f == g;";
+    assert_eq!(output(source), "true\ntrue\nfalse\n");
+    assert_eq!(error("func f() {}\nfunc g() {}\nprint f < g;"), "Error: Invalid operands\n");
+}
+
+#[test]
+fn print_takes_a_whole_comparison() {
+    assert_eq!(output("print 1 < 2; print (1 == 1); print 1 + 1 > 3;"), "true\ntrue\nfalse\n");
+}
+
+#[test]
+fn xor_is_true_when_exactly_one_side_is_truthy() {
+    let source = "print true ^^ true; print true ^^ false;\n\
+                  print false ^^ true; print false ^^ false;";
+    assert_eq!(output(source), "false\ntrue\ntrue\nfalse\n");
+}
+
+#[test]
+fn xor_evaluates_both_operands() {
+    let source = "func side(v) { print \"side\"; return v; }\nprint side(true) ^^ side(true);";
+    assert_eq!(output(source), "side\nside\nfalse\n");
+}
+
+#[test]
+fn an_expression_statement_is_evaluated_for_its_effects() {
+    let source = r#"
+        func init() { print "init"; return 1; }
+        x = nil;
+        x ?? init();
+        y = 2;
+        y ?? init();
+        func yes() { print "yes"; return true; }
+        func no() { print "no"; return false; }
+        yes() ^^ no();
+    "#;
+    assert_eq!(output(source), "init\nyes\nno\n");
+}
+
+#[test]
+fn negative_zero_names_the_same_map_key_as_zero() {
+    let source = "m = {0: \"zero\"};\nprint m[-0]; print -0 in m;\nm[-0] = \"z\"; print m;";
+    assert_eq!(output(source), "zero\ntrue\n{\"0\": \"z\"}\n");
+}
+
+#[test]
+fn a_defer_block_cannot_return_or_stop() {
+    assert_eq!(
+        error("func f() { defer { return 2; } return 1; }\nprint f();"),
+        "Error: Line 1, column 20: 'return' inside a 'defer' block.\n"
+    );
+    assert_eq!(
+        error("defer { stop; }\nprint 1;"),
+        "Error: Line 1, column 9: 'stop' inside a 'defer' block.\n"
+    );
+    let source = "func f() { defer { func g() { return 5; } print g(); } return 1; }\nprint f();";
+    assert_eq!(output(source), "5\n1\n");
+}
@@ -0,0 +1,67 @@
+//! Coarse timing checks. The limits are far above the expected run time in
+//! a debug build; they only catch work that grows with the size of the
+//! program or the depth of scopes on every step.
+
+mod common;
+
+use common::output;
+use std::time::{Duration, Instant};
+
+/// Runs `source`, expecting it to print `expected` within `limit`.
+fn assert_runs_within(limit: Duration, source: &str, expected: &str) {
+    let start = Instant::now();
+    assert_eq!(output(source), expected);
+    let elapsed = start.elapsed();
+    assert!(elapsed < limit, "took {:?}, expected under {:?}", elapsed, limit);
+}
+
+#[test]
+fn calls_do_not_copy_the_function_body() {
+    // 50,000 calls to a function whose body has 5,000 statements, of which
+    // only the first runs
+    let statements: String = (0..5000).map(|i| format!("x = x + {};\n", i)).collect();
+    let source = format!(
+        "func big(x) {{\n if (x > 0) {{ return x; }}\n{}return x;\n}}\n\
+         func run(i, total) {{\n if (i == 0) {{ return total; }}\n\
+         return run(i - 1, total + big(i));\n}}\n\
+         print run(50000, 0);",
+        statements
+    );
+    assert_runs_within(Duration::from_secs(10), &source, "1250025000\n");
+}
+
+#[test]
+fn nested_scopes_do_not_copy_the_environment() {
+    let source = r#"
+        count = 0;
+        i = 0;
+        while (i < 100000) {
+            if (true) {
+                if (true) { count = count + 1; }
+            }
+            i = i + 1;
+        }
+        print count;
+    "#;
+    assert_runs_within(Duration::from_secs(10), source, "100000\n");
+}
+
+#[test]
+fn variable_lookups_through_nested_scopes_stay_fast() {
+    // Reads a shadowing `let`, outer variables and a loop variable on every
+    // step, and checks the shadowed value is untouched afterwards
+    let source = r#"
+        a = 1; b = 2; c = 3;
+        i = 0; total = 0;
+        while (i < 50000) {
+            if (true) {
+                let a = 10;
+                foreach (x in [1]) { total = total + a + b + c + x; }
+            }
+            i = i + 1;
+        }
+        print total;
+        print a;
+    "#;
+    assert_runs_within(Duration::from_secs(10), source, "800000\n1\n");
+}